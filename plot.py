@@ -1,6 +1,7 @@
 import subprocess
-import re
+import json
 import matplotlib.pyplot as plt
+import numpy as np
 import argparse
 from typing import Dict, List, Tuple, Optional
 
@@ -10,65 +11,45 @@ def parse_args():
                        help='Run DRA queue tests with specified number of subqueues and d-choice parameter')
     return parser.parse_args()
 
-def run_command(prefill: int, producer_threads: int, consumer_threads: int, 
-                duration: int, queue_config: str) -> str:
-    cmd = f"cargo r -- --prefill {prefill} --producer-threads {producer_threads} " \
-          f"--consumer-threads {consumer_threads} --duration {duration} {queue_config}"
+QUEUE_NAMES = {
+    'ms-queue': 'MSQueue',
+    'lock-free-queue': 'LockFreeQueue',
+    'crossbeam-queue': 'CrossbeamQueue',
+    'concurrent-queue': 'ConcurrentQueue',
+}
+
+def run_sweep(thread_counts: List[int], relaxed_config: Optional[Tuple[int, int]]) -> List[dict]:
+    threads = ",".join(map(str, thread_counts))
+    strict_queues = ",".join(QUEUE_NAMES)
+    if relaxed_config:
+        subqueues, d_choice = relaxed_config
+        queues = f"--queues dra-queue --subqueue {strict_queues} --subqueues {subqueues} --choice {d_choice}"
+    else:
+        queues = f"--queues {strict_queues}"
+    cmd = f"cargo r --release -- --prefill 1024 --duration 2 --output-format json " \
+          f"sweep --threads {threads} --repetitions 1 --warmup 0 {queues}"
     result = subprocess.run(cmd, shell=True, capture_output=True, text=True)
     if result.returncode != 0:
         print(f"Error running command: {cmd}")
         print(f"Error output: {result.stderr}")
         raise RuntimeError("Command failed")
-    return result.stdout
-
-def parse_output(output: str) -> Tuple[float, int, int]:
-    throughput = float(re.search(r"throughput: ([\d.]+)", output).group(1))
-    enqueues = int(re.search(r"number of enqueues: (\d+)", output).group(1))
-    dequeues = int(re.search(r"number of dequeues: (\d+)", output).group(1))
-    return throughput, enqueues, dequeues
+    return [json.loads(line) for line in result.stdout.splitlines()]
 
-def get_queue_configs(relaxed_config: Optional[Tuple[int, int]] = None) -> Dict[str, str]:
-    if relaxed_config:
-        subqueues, d_choice = relaxed_config
-        base_queues = {
-            'MSQueue': 'ms-queue',
-            'LockFreeQueue': 'lock-free-queue',
-            'CrossbeamQueue': 'crossbeam-queue',
-            'ConcurrentQueue': 'concurrent-queue'
-        }
-        return {
-            f"DRA-{name}-{subqueues}-{d_choice}": f"dra-queue --subqueue {queue} --subqueues {subqueues} --choice {d_choice}"
-            for name, queue in base_queues.items()
-        }
-    else:
-        return {
-            'MSQueue': 'ms-queue',
-            'LockFreeQueue': 'lock-free-queue',
-            'CrossbeamQueue': 'crossbeam-queue',
-            'ConcurrentQueue': 'concurrent-queue'
-        }
+def queue_label(queue: dict) -> str:
+    if queue["name"] == "dra-queue":
+        return f"DRA-{QUEUE_NAMES[queue['subqueue']]}-{queue['subqueues']}-{queue['d']}"
+    return QUEUE_NAMES[queue["name"]]
 
 def run_benchmark(relaxed_config: Optional[Tuple[int, int]] = None) -> Dict[str, List[Tuple[int, float, int, int]]]:
     thread_counts = [1, 2, 4, 8, 16]  # Extended thread counts
-    queue_configs = get_queue_configs(relaxed_config)
     results = {}
-    
-    for queue_name, queue_config in queue_configs.items():
-        queue_results = []
-        print(f"\nRunning benchmarks for {queue_name}...")
-        
-        for thread_count in thread_counts:
-            print(f"Testing with {thread_count} threads...")
-            try:
-                output = run_command(1024, thread_count, thread_count, 2, queue_config)
-                throughput, enqueues, dequeues = parse_output(output)
-                queue_results.append((thread_count, throughput, enqueues, dequeues))
-            except Exception as e:
-                print(f"Error running benchmark: {e}")
-                continue
-                
-        results[queue_name] = queue_results
-    
+    for point in run_sweep(thread_counts, relaxed_config):
+        results.setdefault(queue_label(point["queue"]), []).append((
+            point["config"]["producer_threads"],
+            point["throughput"]["mean"],
+            point["enqueues"]["mean"],
+            point["dequeues"]["mean"],
+        ))
     return results
 
 def plot_results(results: Dict[str, List[Tuple[int, float, int, int]]]):
@@ -0,0 +1,5 @@
+//! Graph algorithms using the queues as their work lists, to see if relaxation pays off in
+//! real applications. Run them with the `apps` binary.
+
+pub mod graph;
+pub mod sssp;
@@ -0,0 +1,216 @@
+//! Graphs in compressed sparse row form, and ways to create them.
+
+use std::{fs, io, path::Path};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// A directed graph with weighted edges.
+pub struct Graph {
+    /// The edges of node `v` are `offsets[v]..offsets[v + 1]`
+    offsets: Vec<usize>,
+    targets: Vec<u32>,
+    weights: Vec<u32>,
+}
+
+impl Graph {
+    /// Builds a graph with `nodes` nodes from `(source, target, weight)` edges.
+    pub fn from_edges(nodes: usize, mut edges: Vec<(u32, u32, u32)>) -> Self {
+        edges.sort_unstable_by_key(|&(source, target, _)| (source, target));
+        let mut offsets = vec![0; nodes + 1];
+        for &(source, _, _) in &edges {
+            offsets[source as usize + 1] += 1;
+        }
+        for v in 0..nodes {
+            offsets[v + 1] += offsets[v];
+        }
+        Self {
+            offsets,
+            targets: edges.iter().map(|&(_, target, _)| target).collect(),
+            weights: edges.iter().map(|&(_, _, weight)| weight).collect(),
+        }
+    }
+
+    pub fn nodes(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    pub fn edges(&self) -> usize {
+        self.targets.len()
+    }
+
+    /// The `(target, weight)` pairs of the outgoing edges of a node.
+    pub fn neighbors(&self, node: u32) -> impl Iterator<Item = (u32, u32)> + '_ {
+        let range = self.offsets[node as usize]..self.offsets[node as usize + 1];
+        self.targets[range.clone()]
+            .iter()
+            .copied()
+            .zip(self.weights[range].iter().copied())
+    }
+
+    /// A random graph with `edges` undirected edges between uniformly chosen nodes.
+    ///
+    /// # Panics
+    ///
+    /// If `nodes` or `max_weight` is zero.
+    pub fn random(nodes: usize, edges: usize, max_weight: u32, seed: u64) -> Self {
+        check_generated(nodes, max_weight);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let edges = (0..edges)
+            .map(|_| {
+                let source = rng.gen_range(0..nodes) as u32;
+                let target = rng.gen_range(0..nodes) as u32;
+                (source, target, rng.gen_range(1..=max_weight))
+            })
+            .collect();
+        Self::from_edges(nodes, undirected(edges))
+    }
+
+    /// A `width` by `height` grid where every node is connected to its four neighbors.
+    ///
+    /// # Panics
+    ///
+    /// If `width`, `height` or `max_weight` is zero.
+    pub fn grid(width: usize, height: usize, max_weight: u32, seed: u64) -> Self {
+        check_generated(width * height, max_weight);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let node = |x: usize, y: usize| (y * width + x) as u32;
+        let mut edges = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                if x + 1 < width {
+                    edges.push((node(x, y), node(x + 1, y), rng.gen_range(1..=max_weight)));
+                }
+                if y + 1 < height {
+                    edges.push((node(x, y), node(x, y + 1), rng.gen_range(1..=max_weight)));
+                }
+            }
+        }
+        Self::from_edges(width * height, undirected(edges))
+    }
+
+    /// A scale-free graph from the Barabási–Albert model, where every new node connects to
+    /// `degree` existing nodes chosen proportionally to their degree.
+    ///
+    /// # Panics
+    ///
+    /// If `nodes` or `max_weight` is zero.
+    pub fn power_law(nodes: usize, degree: usize, max_weight: u32, seed: u64) -> Self {
+        check_generated(nodes, max_weight);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut edges = Vec::new();
+        // Every edge adds both its endpoints, so sampling from it picks nodes by degree
+        let mut endpoints: Vec<u32> = Vec::new();
+        for node in 1..nodes as u32 {
+            for _ in 0..degree.min(node as usize) {
+                let target = if endpoints.is_empty() {
+                    0
+                } else {
+                    endpoints[rng.gen_range(0..endpoints.len())]
+                };
+                edges.push((node, target, rng.gen_range(1..=max_weight)));
+                endpoints.extend([node, target]);
+            }
+        }
+        Self::from_edges(nodes, undirected(edges))
+    }
+
+    /// Loads a directed graph from a file with one `source target [weight]` edge per line.
+    /// Empty lines and lines starting with `#` or `%` are skipped, and missing weights are 1.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(text: &str) -> io::Result<Self> {
+        let invalid = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid edge line: {line}"),
+            )
+        };
+        let mut edges = Vec::new();
+        let mut nodes = 0;
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with(['#', '%']) {
+                continue;
+            }
+            let fields = line
+                .split_whitespace()
+                .map(|field| field.parse::<u32>().map_err(|_| invalid(line)))
+                .collect::<Result<Vec<_>, _>>()?;
+            let (source, target, weight) = match fields[..] {
+                [source, target] => (source, target, 1),
+                [source, target, weight] => (source, target, weight),
+                _ => return Err(invalid(line)),
+            };
+            nodes = nodes.max(source.max(target) as usize + 1);
+            edges.push((source, target, weight));
+        }
+        Ok(Self::from_edges(nodes, edges))
+    }
+}
+
+/// Generated graphs need a node to start from, and weights are drawn from `1..=max_weight`.
+fn check_generated(nodes: usize, max_weight: u32) {
+    assert!(nodes > 0, "a generated graph needs at least one node");
+    assert!(
+        max_weight > 0,
+        "the largest edge weight has to be at least 1"
+    );
+}
+
+fn undirected(mut edges: Vec<(u32, u32, u32)>) -> Vec<(u32, u32, u32)> {
+    let reversed: Vec<_> = edges
+        .iter()
+        .map(|&(source, target, weight)| (target, source, weight))
+        .collect();
+    edges.extend(reversed);
+    edges
+}
+
+#[cfg(test)]
+mod test {
+    use super::Graph;
+
+    #[test]
+    fn grid_has_four_neighbors_inside() {
+        let graph = Graph::grid(3, 3, 1, 0);
+        assert_eq!(graph.nodes(), 9);
+        // 12 undirected edges
+        assert_eq!(graph.edges(), 24);
+        let mut center: Vec<_> = graph.neighbors(4).map(|(target, _)| target).collect();
+        center.sort_unstable();
+        assert_eq!(center, [1, 3, 5, 7]);
+        assert_eq!(graph.neighbors(0).count(), 2);
+    }
+
+    #[test]
+    fn generators_respect_sizes_and_weights() {
+        let random = Graph::random(100, 300, 10, 1);
+        assert_eq!((random.nodes(), random.edges()), (100, 600));
+        let power_law = Graph::power_law(100, 3, 10, 1);
+        assert_eq!(power_law.nodes(), 100);
+        // The first nodes have fewer earlier nodes to connect to
+        assert_eq!(power_law.edges(), 2 * (1 + 2 + 3 * 97));
+        for graph in [random, power_law] {
+            for node in 0..graph.nodes() as u32 {
+                assert!(graph.neighbors(node).all(|(_, w)| (1..=10).contains(&w)));
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one node")]
+    fn rejects_empty_generated_graphs() {
+        Graph::power_law(0, 3, 10, 1);
+    }
+
+    #[test]
+    fn parses_edge_lists() {
+        let graph = Graph::parse("# comment\n0 1\n1 2 5\n\n% other comment\n2 0 3\n").unwrap();
+        assert_eq!((graph.nodes(), graph.edges()), (3, 3));
+        assert_eq!(graph.neighbors(1).collect::<Vec<_>>(), [(2, 5)]);
+        assert_eq!(graph.neighbors(0).collect::<Vec<_>>(), [(1, 1)]);
+        assert!(Graph::parse("0 1 2 3\n").is_err());
+        assert!(Graph::parse("0 x\n").is_err());
+    }
+}
@@ -0,0 +1,218 @@
+//! Parallel single-source shortest paths and breadth-first search, with a concurrent queue as
+//! the work list.
+//!
+//! The algorithm is label-correcting: a node is pushed whenever its distance improves, so with
+//! a FIFO queue it behaves like parallel Bellman-Ford, and the more a queue reorders items the
+//! more often nodes get relaxed again with a better distance.
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{ConcurrentQueue, Handle};
+
+use super::graph::Graph;
+
+/// The distance of nodes which can not be reached from the source.
+pub const UNREACHED: u64 = u64::MAX;
+
+/// The outcome of a parallel run.
+pub struct Stats {
+    pub distances: Vec<u64>,
+    pub wall_time: Duration,
+    /// Dequeued nodes which were expanded
+    pub processed: usize,
+    /// Dequeued nodes which had already been reached with a shorter distance, and were skipped
+    pub stale: usize,
+    /// Successful distance improvements, each of which enqueued a node
+    pub relaxations: usize,
+}
+
+impl Stats {
+    pub fn reached(&self) -> usize {
+        self.distances.iter().filter(|&&d| d != UNREACHED).count()
+    }
+
+    /// Relaxations beyond the one every reached node needs, which is the work wasted because
+    /// nodes were not processed in order of distance.
+    pub fn re_relaxations(&self) -> usize {
+        // The source is reached without a relaxation
+        self.relaxations + 1 - self.reached()
+    }
+}
+
+/// Computes the weighted distances from `source` using `threads` threads.
+pub fn sssp<Q>(graph: &Graph, source: u32, queue: &Q, threads: usize) -> Stats
+where
+    Q: ConcurrentQueue<(u32, u64)> + Sync,
+{
+    shortest_paths(graph, source, queue, threads, |weight| weight as u64)
+}
+
+/// Computes the number of hops from `source` using `threads` threads, ignoring the weights.
+pub fn bfs<Q>(graph: &Graph, source: u32, queue: &Q, threads: usize) -> Stats
+where
+    Q: ConcurrentQueue<(u32, u64)> + Sync,
+{
+    shortest_paths(graph, source, queue, threads, |_| 1)
+}
+
+fn shortest_paths<Q>(
+    graph: &Graph,
+    source: u32,
+    queue: &Q,
+    threads: usize,
+    length: fn(u32) -> u64,
+) -> Stats
+where
+    Q: ConcurrentQueue<(u32, u64)> + Sync,
+{
+    let distances: Vec<_> = (0..graph.nodes())
+        .map(|_| AtomicU64::new(UNREACHED))
+        .collect();
+    distances[source as usize].store(0, Ordering::Relaxed);
+    // Nodes in the queue or being processed. Relaxed queues can fail to find items, so an empty
+    // dequeue alone does not mean that the search is done.
+    let pending = AtomicUsize::new(1);
+    queue.register().enqueue((source, 0));
+
+    let start = Instant::now();
+    let counts: Vec<_> = thread::scope(|s| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                let (distances, pending) = (&distances, &pending);
+                s.spawn(move || {
+                    let mut handle = queue.register();
+                    let (mut processed, mut stale, mut relaxations) = (0, 0, 0);
+                    loop {
+                        let Some((node, distance)) = handle.dequeue() else {
+                            if pending.load(Ordering::Acquire) == 0 {
+                                break;
+                            }
+                            std::hint::spin_loop();
+                            continue;
+                        };
+                        if distance > distances[node as usize].load(Ordering::Relaxed) {
+                            stale += 1;
+                        } else {
+                            processed += 1;
+                            for (target, weight) in graph.neighbors(node) {
+                                let new = distance + length(weight);
+                                let old =
+                                    distances[target as usize].fetch_min(new, Ordering::Relaxed);
+                                if new < old {
+                                    relaxations += 1;
+                                    pending.fetch_add(1, Ordering::Relaxed);
+                                    handle.enqueue((target, new));
+                                }
+                            }
+                        }
+                        pending.fetch_sub(1, Ordering::Release);
+                    }
+                    (processed, stale, relaxations)
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect()
+    });
+    let wall_time = start.elapsed();
+
+    Stats {
+        distances: distances.into_iter().map(AtomicU64::into_inner).collect(),
+        wall_time,
+        processed: counts.iter().map(|c| c.0).sum(),
+        stale: counts.iter().map(|c| c.1).sum(),
+        relaxations: counts.iter().map(|c| c.2).sum(),
+    }
+}
+
+/// Sequential Dijkstra, to check the parallel results against.
+pub fn dijkstra(graph: &Graph, source: u32) -> Vec<u64> {
+    let mut distances = vec![UNREACHED; graph.nodes()];
+    let mut heap = BinaryHeap::from([Reverse((0, source))]);
+    distances[source as usize] = 0;
+    while let Some(Reverse((distance, node))) = heap.pop() {
+        if distance > distances[node as usize] {
+            continue;
+        }
+        for (target, weight) in graph.neighbors(node) {
+            let new = distance + weight as u64;
+            if new < distances[target as usize] {
+                distances[target as usize] = new;
+                heap.push(Reverse((new, target)));
+            }
+        }
+    }
+    distances
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use crate::{
+        apps::graph::Graph,
+        relaxed_queues::{dra_queue::DRaQueue, round_robin_queue::RoundRobinQueue},
+        strict_queues::{countable_wrapper::CountableWrapper, ms::MSQueue},
+        ConcurrentQueue,
+    };
+
+    use super::{bfs, dijkstra, sssp};
+
+    const THREADS: usize = if cfg!(miri) { 2 } else { 4 };
+    const SIDE: usize = if cfg!(miri) { 4 } else { 30 };
+
+    fn graphs() -> Vec<Graph> {
+        let nodes = SIDE * SIDE;
+        vec![
+            Graph::grid(SIDE, SIDE, 10, 0),
+            Graph::random(nodes, 2 * nodes, 10, 0),
+            Graph::power_law(nodes, 2, 10, 0),
+        ]
+    }
+
+    fn matches_dijkstra<Q: ConcurrentQueue<(u32, u64)> + Sync>(new_queue: impl Fn() -> Q) {
+        for graph in graphs() {
+            let stats = sssp(&graph, 0, &new_queue(), THREADS);
+            assert_eq!(stats.distances, dijkstra(&graph, 0));
+            assert_eq!(stats.processed + stats.stale, stats.relaxations + 1);
+
+            let unweighted = Graph::from_edges(
+                graph.nodes(),
+                (0..graph.nodes() as u32)
+                    .flat_map(|v| graph.neighbors(v).map(move |(t, _)| (v, t, 1)))
+                    .collect(),
+            );
+            let stats = bfs(&graph, 0, &new_queue(), THREADS);
+            assert_eq!(stats.distances, dijkstra(&unweighted, 0));
+        }
+    }
+
+    #[test]
+    fn ms_queue_matches_dijkstra() {
+        matches_dijkstra(MSQueue::new);
+    }
+
+    #[test]
+    fn dra_queue_matches_dijkstra() {
+        matches_dijkstra(|| DRaQueue::<CountableWrapper<MSQueue<_>>, _>::new(4, 2));
+    }
+
+    #[test]
+    fn round_robin_queue_matches_dijkstra() {
+        matches_dijkstra(|| RoundRobinQueue::<MSQueue<_>, _>::new(4));
+    }
+
+    #[test]
+    fn single_thread_bfs_with_fifo_does_no_wasted_work() {
+        let graph = Graph::grid(SIDE, SIDE, 10, 0);
+        let stats = bfs(&graph, 0, &MSQueue::new(), 1);
+        assert_eq!(stats.reached(), graph.nodes());
+        assert_eq!((stats.re_relaxations(), stats.stale), (0, 0));
+    }
+}
@@ -0,0 +1,331 @@
+//! Async dequeues for any queue, and [`Stream`]/[`Sink`] adapters for its handles.
+//!
+//! A dequeue which finds nothing registers the waker of its task on the same [`EventCount`] as
+//! [`blocking`](crate::blocking) uses, and looks once more before returning pending. The
+//! registration ends when the task polls again, or when the future or handle is dropped.
+//! Enqueuers only pay for a fence and a load of the waiter count unless a task waits.
+
+use std::{
+    convert::Infallible,
+    future::Future,
+    mem,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+
+use futures::{Sink, Stream};
+
+use crate::{eventcount::EventCount, ConcurrentQueue, Handle, HandleStats};
+
+/// How many dequeues to try while the queue holds items, before waiting for the next enqueue.
+const RETRIES: usize = 100;
+
+/// A queue whose handles can be awaited on, see [`AsyncHandle`].
+pub struct Async<Q> {
+    queue: Q,
+    /// Counts items from before they are enqueued until they are dequeued, so a task retries
+    /// while the dequeues of a [`Relaxed`](crate::Relaxed) queue miss items
+    items: AtomicUsize,
+    event: EventCount,
+}
+
+impl<Q> Async<Q> {
+    pub fn new(queue: Q) -> Self {
+        Self {
+            queue,
+            items: AtomicUsize::new(0),
+            event: EventCount::new(),
+        }
+    }
+}
+
+impl<T, Q: ConcurrentQueue<T>> ConcurrentQueue<T> for Async<Q> {
+    type QueueType = Q::QueueType;
+    type Handle<'q>
+        = AsyncHandle<'q, T, Q>
+    where
+        Self: 'q;
+
+    fn register(&self) -> AsyncHandle<'_, T, Q> {
+        AsyncHandle {
+            handle: self.queue.register(),
+            queue: self,
+            id: self.event.new_id(),
+            waiting: false,
+        }
+    }
+
+    fn register_with_stream(&self, stream: usize) -> AsyncHandle<'_, T, Q> {
+        AsyncHandle {
+            handle: self.queue.register_with_stream(stream),
+            queue: self,
+            id: self.event.new_id(),
+            waiting: false,
+        }
+    }
+}
+
+/// A handle of an [`Async`] queue. Enqueues wake the tasks waiting in
+/// [`dequeue_async`](Self::dequeue_async), and the handle is a never ending [`Stream`] of the
+/// items it dequeues and a [`Sink`] for the items it enqueues.
+pub struct AsyncHandle<'q, T, Q: ConcurrentQueue<T> + 'q> {
+    handle: Q::Handle<'q>,
+    queue: &'q Async<Q>,
+    id: usize,
+    /// Whether the waker of a pending poll is registered
+    waiting: bool,
+}
+
+impl<'q, T, Q: ConcurrentQueue<T>> AsyncHandle<'q, T, Q> {
+    /// Dequeues an item, waiting for one to be enqueued if the queue is empty.
+    pub fn dequeue_async(&mut self) -> Dequeue<'_, 'q, T, Q> {
+        Dequeue { handle: self }
+    }
+
+    /// Dequeues an item, or registers the waker of the task to be woken by the next enqueue.
+    pub fn poll_dequeue(&mut self, cx: &mut Context<'_>) -> Poll<T> {
+        self.stop_waiting();
+        loop {
+            if let Some(item) = self.dequeue_retrying() {
+                return Poll::Ready(item);
+            }
+            let key = self.queue.event.prepare_wait();
+            self.waiting = true;
+            if let Some(item) = self.dequeue() {
+                self.stop_waiting();
+                return Poll::Ready(item);
+            }
+            if self.queue.event.register(self.id, key, cx.waker()) {
+                return Poll::Pending;
+            }
+            // Something was enqueued since the last look
+            self.stop_waiting();
+        }
+    }
+
+    /// Dequeues an item, retrying a bounded number of times while the queue holds items which
+    /// the dequeues miss.
+    fn dequeue_retrying(&mut self) -> Option<T> {
+        for _ in 0..RETRIES {
+            if let Some(item) = self.dequeue() {
+                return Some(item);
+            }
+            if self.queue.items.load(Ordering::SeqCst) == 0 {
+                break;
+            }
+        }
+        None
+    }
+
+    /// Unregisters the waker of a pending poll.
+    fn stop_waiting(&mut self) {
+        if mem::take(&mut self.waiting) {
+            self.queue.event.cancel_wait(self.id);
+        }
+    }
+}
+
+impl<'q, T, Q: ConcurrentQueue<T> + 'q> Drop for AsyncHandle<'q, T, Q> {
+    fn drop(&mut self) {
+        self.stop_waiting();
+    }
+}
+
+impl<T, Q: ConcurrentQueue<T>> Handle<T> for AsyncHandle<'_, T, Q> {
+    fn enqueue(&mut self, item: T) {
+        self.queue.items.fetch_add(1, Ordering::SeqCst);
+        self.handle.enqueue(item);
+        self.queue.event.notify();
+    }
+
+    fn dequeue(&mut self) -> Option<T> {
+        let item = self.handle.dequeue();
+        if item.is_some() {
+            self.queue.items.fetch_sub(1, Ordering::SeqCst);
+        }
+        item
+    }
+
+    fn enqueue_batch(&mut self, items: Vec<T>) {
+        self.queue.items.fetch_add(items.len(), Ordering::SeqCst);
+        self.handle.enqueue_batch(items);
+        self.queue.event.notify();
+    }
+
+    fn dequeue_batch(&mut self, max: usize) -> Vec<T> {
+        let items = self.handle.dequeue_batch(max);
+        self.queue.items.fetch_sub(items.len(), Ordering::SeqCst);
+        items
+    }
+
+    fn stats(&self) -> HandleStats {
+        self.handle.stats()
+    }
+}
+
+/// The future of [`AsyncHandle::dequeue_async`].
+pub struct Dequeue<'h, 'q, T, Q: ConcurrentQueue<T> + 'q> {
+    handle: &'h mut AsyncHandle<'q, T, Q>,
+}
+
+impl<T, Q: ConcurrentQueue<T>> Future for Dequeue<'_, '_, T, Q> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        self.get_mut().handle.poll_dequeue(cx)
+    }
+}
+
+/// A dequeue which is given up on no longer waits.
+impl<'q, T, Q: ConcurrentQueue<T> + 'q> Drop for Dequeue<'_, 'q, T, Q> {
+    fn drop(&mut self) {
+        self.handle.stop_waiting();
+    }
+}
+
+impl<'q, T, Q: ConcurrentQueue<T>> Stream for AsyncHandle<'q, T, Q>
+where
+    Q::Handle<'q>: Unpin,
+{
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.get_mut().poll_dequeue(cx).map(Some)
+    }
+}
+
+/// Enqueues never wait, so the sink is always ready and has nothing to flush.
+impl<'q, T, Q: ConcurrentQueue<T>> Sink<T> for AsyncHandle<'q, T, Q>
+where
+    Q::Handle<'q>: Unpin,
+{
+    type Error = Infallible;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Infallible> {
+        self.get_mut().enqueue(item);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use std::{
+        future::Future,
+        pin::pin,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        task::{Context, Poll, Wake, Waker},
+        thread,
+        time::Duration,
+    };
+
+    use futures::{
+        executor::{block_on, LocalPool},
+        future, stream, SinkExt, StreamExt,
+    };
+
+    use crate::{
+        conformance::queue_conformance_tests,
+        relaxed_queues::dra_queue::DRaQueue,
+        strict_queues::{countable_wrapper::CountableWrapper, ms::MSQueue},
+        ConcurrentQueue, Handle,
+    };
+
+    use super::Async;
+
+    mod ms {
+        use super::*;
+
+        queue_conformance_tests!(strict, Async::new(MSQueue::new()));
+    }
+
+    mod dra {
+        use super::*;
+
+        queue_conformance_tests!(
+            relaxed,
+            Async::new(DRaQueue::<CountableWrapper<MSQueue<_>>, _>::new(4, 2))
+        );
+    }
+
+    #[test]
+    fn wakes_pending_dequeue() {
+        let queue = Async::new(MSQueue::new());
+        let mut handle = queue.register();
+        // The dequeue is polled first and finds nothing, so only the enqueue can wake it
+        let (item, ()) = LocalPool::new().run_until(future::join(handle.dequeue_async(), async {
+            queue.register().enqueue(1)
+        }));
+        assert_eq!(item, 1);
+    }
+
+    /// Counts how often it was woken.
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn dropped_dequeue_stops_waiting() {
+        let queue = Async::new(MSQueue::new());
+        let wakes = Arc::new(CountingWaker::default());
+        let waker = Waker::from(wakes.clone());
+        let mut handle = queue.register();
+        {
+            let mut dequeue = pin!(handle.dequeue_async());
+            let poll = dequeue.as_mut().poll(&mut Context::from_waker(&waker));
+            assert_eq!(poll, Poll::Pending);
+            assert_eq!(queue.event.waiters(), 1);
+        }
+        assert_eq!(queue.event.waiters(), 0);
+        queue.register().enqueue(1);
+        // Neither the pending poll nor the enqueue after the drop woke the task
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 0);
+        assert_eq!(handle.dequeue(), Some(1));
+    }
+
+    #[test]
+    fn waits_for_other_threads() {
+        let queue = Async::new(MSQueue::new());
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                queue.register().enqueue(1);
+            });
+            assert_eq!(block_on(queue.register().dequeue_async()), 1);
+        });
+    }
+
+    #[test]
+    fn streams_what_sinks_send() {
+        const ITEMS: usize = 100;
+        let queue = Async::new(DRaQueue::<CountableWrapper<MSQueue<_>>, _>::new(4, 2));
+        let mut sink = queue.register();
+        let (mut items, sent) = LocalPool::new().run_until(future::join(
+            queue.register().take(ITEMS).collect::<Vec<_>>(),
+            sink.send_all(&mut stream::iter((0..ITEMS).map(Ok))),
+        ));
+        sent.unwrap();
+        items.sort_unstable();
+        assert_eq!(items, (0..ITEMS).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,125 @@
+//! The multi-threaded throughput benchmark.
+
+pub mod latency;
+pub mod memory;
+pub mod output;
+pub mod payload;
+pub mod perf;
+pub mod placement;
+pub mod scenario;
+pub mod sweep;
+pub mod work;
+pub mod workload;
+
+use core_affinity::CoreId;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Barrier,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use relaxed_queues::{drain, ConcurrentQueue, Handle};
+
+use crate::BenchConfig;
+use memory::{MemoryUsage, Sampler};
+use payload::Payload;
+use perf::Counters;
+use workload::ThreadResult;
+
+/// How often the memory use is sampled during the measured window.
+const MEMORY_SAMPLE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What a benchmark run measured.
+pub struct Run {
+    pub threads: Vec<ThreadResult>,
+    pub memory: MemoryUsage,
+    /// The number of items drained from the queue after the run
+    pub final_queue_size: usize,
+}
+
+/// Runs the configured workload on the queue, returning what every thread did, the memory use of
+/// the process and what was left in the queue.
+pub fn benchmark<P, C>(queue: C, config: &BenchConfig) -> Run
+where
+    P: Payload,
+    C: ConcurrentQueue<P> + Sync,
+{
+    let mut handle = queue.register();
+    for i in 0..config.prefill {
+        handle.enqueue(P::new(i, config.payload.payload_size));
+    }
+
+    let (producers, consumers) = config.threads();
+    let threads = config.workload.threads(producers, consumers);
+    let done: AtomicBool = AtomicBool::new(false);
+    let barrier = Barrier::new(threads.len() + 1);
+
+    let cpus = config
+        .placement
+        .cpus(&placement::topology(), threads.len(), producers);
+    thread::scope(|s| {
+        // To get move semantict for thread closures
+        let queue = &queue;
+        let done = &done;
+        let barrier = &barrier;
+
+        let workers: Vec<_> = threads
+            .into_iter()
+            .zip(cpus)
+            .enumerate()
+            .map(|(index, (workload, cpu))| {
+                s.spawn(move || {
+                    if let Some(id) = cpu {
+                        core_affinity::set_for_current(CoreId { id });
+                    }
+                    // Every worker gets the same random stream of a seeded queue in every run
+                    let mut handle = queue.register_with_stream(index);
+                    let counters = config.perf.then(Counters::open);
+                    barrier.wait();
+                    // Counters which could not be enabled would report zeros, so they are left out
+                    let counters = counters.filter(|counters| match counters.enable() {
+                        Ok(()) => true,
+                        Err(error) => {
+                            eprintln!("warning: could not enable the perf events: {error}");
+                            false
+                        }
+                    });
+                    let mut result = workload.run(&mut handle, done, config);
+                    if let Some(counters) = counters {
+                        match counters.disable() {
+                            Ok(()) => result.perf = Some(counters.read()),
+                            Err(error) => {
+                                eprintln!("warning: could not disable the perf events: {error}")
+                            }
+                        }
+                    }
+                    result.cpu = cpu;
+                    result
+                })
+            })
+            .collect();
+
+        let mut memory = Sampler::new();
+        barrier.wait();
+        let end = Instant::now() + Duration::from_secs(config.duration() as u64);
+        while let Some(left) = end.checked_duration_since(Instant::now()) {
+            thread::sleep(left.min(MEMORY_SAMPLE_INTERVAL));
+            memory.sample();
+        }
+        done.store(true, Ordering::Relaxed);
+
+        let threads = workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect();
+        let memory = memory.finish();
+        Run {
+            threads,
+            memory,
+            final_queue_size: drain(|| handle.dequeue()).len(),
+        }
+    })
+}
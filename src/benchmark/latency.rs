@@ -0,0 +1,190 @@
+//! Log-bucketed latency histograms.
+
+use std::fmt;
+
+use serde::Serialize;
+
+/// Every power of two is split into this many buckets, which bounds the relative error of a
+/// recorded value by 1 / SUB_BUCKETS.
+const SUB_BUCKET_BITS: u32 = 4;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+const BUCKETS: usize = (64 - SUB_BUCKET_BITS as usize + 1) << SUB_BUCKET_BITS;
+
+/// A histogram of latencies in nanoseconds.
+#[derive(Clone)]
+pub struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self {
+            buckets: vec![0; BUCKETS],
+            count: 0,
+            max: 0,
+        }
+    }
+
+    pub fn record(&mut self, nanos: u64) {
+        self.buckets[bucket(nanos)] += 1;
+        self.count += 1;
+        self.max = self.max.max(nanos);
+    }
+
+    /// Adds all values recorded in another histogram.
+    pub fn merge(&mut self, other: &Histogram) {
+        for (bucket, count) in self.buckets.iter_mut().zip(&other.buckets) {
+            *bucket += count;
+        }
+        self.count += other.count;
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// The smallest bucket bound which at least `percentile` percent of the values are below,
+    /// or 0 if the histogram is empty.
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        let rank = ((percentile / 100.0 * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return upper_bound(index).min(self.max);
+            }
+        }
+        0
+    }
+}
+
+/// The percentiles of a histogram included in reports.
+#[derive(Serialize, Clone, Copy)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub p99_9: u64,
+    pub max: u64,
+}
+
+impl Histogram {
+    pub fn summary(&self) -> LatencySummary {
+        LatencySummary {
+            count: self.count(),
+            p50: self.percentile(50.0),
+            p90: self.percentile(90.0),
+            p99: self.percentile(99.0),
+            p99_9: self.percentile(99.9),
+            max: self.max(),
+        }
+    }
+}
+
+impl fmt::Display for LatencySummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "count: {}, p50: {}, p90: {}, p99: {}, p99.9: {}, max: {}",
+            self.count, self.p50, self.p90, self.p99, self.p99_9, self.max
+        )
+    }
+}
+
+/// The bucket index of a value. Values below [`SUB_BUCKETS`] get a bucket each, and above that
+/// every power of two is split into [`SUB_BUCKETS`] equally wide buckets.
+fn bucket(value: u64) -> usize {
+    if value < SUB_BUCKETS {
+        return value as usize;
+    }
+    let exponent = 63 - value.leading_zeros();
+    let shift = exponent - SUB_BUCKET_BITS;
+    let sub_bucket = (value >> shift) & (SUB_BUCKETS - 1);
+    (((shift + 1) as u64) << SUB_BUCKET_BITS | sub_bucket) as usize
+}
+
+/// The largest value that falls into the bucket.
+fn upper_bound(bucket: usize) -> u64 {
+    let bucket = bucket as u64;
+    if bucket < SUB_BUCKETS {
+        return bucket;
+    }
+    let shift = (bucket >> SUB_BUCKET_BITS) - 1;
+    let sub_bucket = bucket & (SUB_BUCKETS - 1);
+    ((SUB_BUCKETS + sub_bucket) << shift) + ((1 << shift) - 1)
+}
+
+/// The latency histograms of each operation type.
+#[derive(Clone, Default)]
+pub struct Latencies {
+    pub enqueue: Histogram,
+    pub dequeue: Histogram,
+}
+
+impl Latencies {
+    pub fn merge(&mut self, other: &Latencies) {
+        self.enqueue.merge(&other.enqueue);
+        self.dequeue.merge(&other.dequeue);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bucket, upper_bound, Histogram, BUCKETS};
+
+    #[test]
+    fn buckets_are_contiguous() {
+        for value in 0..100_000 {
+            let index = bucket(value);
+            assert!(value <= upper_bound(index));
+            assert!(index == 0 || value > upper_bound(index - 1));
+        }
+        assert_eq!(bucket(u64::MAX), BUCKETS - 1);
+        assert_eq!(upper_bound(BUCKETS - 1), u64::MAX);
+    }
+
+    #[test]
+    fn percentiles_are_within_bucket_precision() {
+        let mut histogram = Histogram::new();
+        for value in 1..=10_000 {
+            histogram.record(value);
+        }
+        for (percentile, exact) in [(50.0, 5_000), (90.0, 9_000), (99.0, 9_900)] {
+            let estimate = histogram.percentile(percentile);
+            assert!(
+                estimate >= exact && estimate <= exact + exact / 16,
+                "{estimate}"
+            );
+        }
+        assert_eq!(histogram.percentile(100.0), 10_000);
+        assert_eq!(histogram.max(), 10_000);
+    }
+
+    #[test]
+    fn merge_adds_counts() {
+        let mut first = Histogram::new();
+        let mut second = Histogram::new();
+        first.record(10);
+        second.record(1_000);
+        second.record(1_000);
+        first.merge(&second);
+        assert_eq!(first.count(), 3);
+        assert_eq!(first.percentile(30.0), 10);
+        assert_eq!(first.max(), 1_000);
+        assert_eq!(Histogram::new().percentile(50.0), 0);
+    }
+}
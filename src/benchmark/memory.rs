@@ -0,0 +1,194 @@
+//! The memory use of the process, sampled before, during and after the measured window.
+
+use std::{fmt, fs};
+
+use serde::Serialize;
+
+/// The memory use of the process at one point in time. Either value is missing where it can not
+/// be measured.
+#[derive(Serialize, Default, Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    /// Bytes allocated through jemalloc
+    pub allocated: Option<u64>,
+    /// Resident set size in bytes
+    pub rss: Option<u64>,
+}
+
+impl Sample {
+    pub fn take() -> Self {
+        Self {
+            allocated: allocated(),
+            rss: rss(),
+        }
+    }
+
+    fn max(self, other: Self) -> Self {
+        Self {
+            allocated: self.allocated.max(other.allocated),
+            rss: self.rss.max(other.rss),
+        }
+    }
+
+    fn mean(samples: &[Sample]) -> Self {
+        let mean = |value: fn(&Sample) -> Option<u64>| {
+            let values: Option<Vec<u64>> = samples.iter().map(value).collect();
+            values
+                .filter(|values| !values.is_empty())
+                .map(|values| values.iter().sum::<u64>() / values.len() as u64)
+        };
+        Self {
+            allocated: mean(|sample| sample.allocated),
+            rss: mean(|sample| sample.rss),
+        }
+    }
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mib = |bytes: Option<u64>| match bytes {
+            Some(bytes) => format!("{:.1} MiB", bytes as f64 / (1 << 20) as f64),
+            None => "unknown".to_string(),
+        };
+        write!(
+            f,
+            "allocated: {}, rss: {}",
+            mib(self.allocated),
+            mib(self.rss)
+        )
+    }
+}
+
+/// A summary of the samples of a run.
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct MemoryUsage {
+    /// After the prefill, before the threads start
+    pub before: Sample,
+    /// The largest value seen of each measure
+    pub peak: Sample,
+    /// The mean of the samples in the second half of the measured window
+    pub steady: Sample,
+    /// After the threads are done, before the queue is dropped
+    pub after: Sample,
+}
+
+/// Collects samples over a run.
+pub struct Sampler {
+    before: Sample,
+    samples: Vec<Sample>,
+}
+
+impl Sampler {
+    pub fn new() -> Self {
+        Self {
+            before: Sample::take(),
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn sample(&mut self) {
+        self.samples.push(Sample::take());
+    }
+
+    pub fn finish(self) -> MemoryUsage {
+        summarize(self.before, &self.samples, Sample::take())
+    }
+}
+
+fn summarize(before: Sample, samples: &[Sample], after: Sample) -> MemoryUsage {
+    let peak = samples
+        .iter()
+        .fold(before.max(after), |peak, &sample| peak.max(sample));
+    let steady = match &samples[samples.len() / 2..] {
+        [] => after,
+        second_half => Sample::mean(second_half),
+    };
+    MemoryUsage {
+        before,
+        peak,
+        steady,
+        after,
+    }
+}
+
+#[cfg(not(target_env = "msvc"))]
+fn allocated() -> Option<u64> {
+    use std::{ffi::c_void, mem::size_of, ptr};
+
+    let mut epoch: u64 = 1;
+    let mut allocated: usize = 0;
+    let mut len = size_of::<usize>();
+    // SAFETY: the names are valid mallctl names, and the values have the types they expect
+    let read = unsafe {
+        // The statistics are only refreshed when the epoch is advanced
+        jemalloc_sys::mallctl(
+            c"epoch".as_ptr(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            &mut epoch as *mut u64 as *mut c_void,
+            size_of::<u64>(),
+        );
+        jemalloc_sys::mallctl(
+            c"stats.allocated".as_ptr(),
+            &mut allocated as *mut usize as *mut c_void,
+            &mut len,
+            ptr::null_mut(),
+            0,
+        )
+    };
+    (read == 0).then_some(allocated as u64)
+}
+
+#[cfg(target_env = "msvc")]
+fn allocated() -> Option<u64> {
+    None
+}
+
+/// The resident set size from `/proc/self/status`, which only exists on Linux.
+fn rss() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{summarize, Sample};
+
+    fn sample(allocated: u64, rss: u64) -> Sample {
+        Sample {
+            allocated: Some(allocated),
+            rss: Some(rss),
+        }
+    }
+
+    #[test]
+    fn summarizes_samples() {
+        let samples = [
+            sample(10, 50),
+            sample(30, 40),
+            sample(20, 60),
+            sample(40, 80),
+        ];
+        let usage = summarize(sample(5, 30), &samples, sample(35, 70));
+        assert_eq!(usage.peak, sample(40, 80));
+        assert_eq!(usage.steady, sample(30, 70));
+
+        let usage = summarize(sample(5, 30), &[], sample(35, 70));
+        assert_eq!(usage.steady, sample(35, 70));
+    }
+
+    #[test]
+    fn measures_allocations() {
+        let before = Sample::take();
+        let buffer = vec![1u8; 64 << 20];
+        let after = Sample::take();
+        drop(buffer);
+        if let (Some(before), Some(after)) = (before.allocated, after.allocated) {
+            assert!(after >= before + (32 << 20), "{before} -> {after}");
+        }
+        if cfg!(target_os = "linux") {
+            assert!(after.rss.is_some());
+        }
+    }
+}
@@ -0,0 +1,338 @@
+//! Benchmark results, and how they are printed.
+
+use std::collections::BTreeMap;
+
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{BenchConfig, QueueConfig};
+
+use super::{
+    latency::{Latencies, LatencySummary},
+    memory::MemoryUsage,
+    workload::{Counts, ThreadResult},
+    Run,
+};
+
+#[derive(ValueEnum, Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Human readable lines
+    Text,
+    /// One JSON object per run
+    Json,
+    /// A header followed by one row per run. Per-thread values are joined by `;`
+    Csv,
+}
+
+/// Everything measured in a single benchmark run.
+#[derive(Serialize)]
+pub struct Record<'c> {
+    /// The name of the scenario the run belongs to, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scenario: Option<String>,
+    pub config: &'c BenchConfig,
+    pub queue: QueueConfig,
+    pub metrics: Metrics,
+    pub threads: Vec<ThreadResult>,
+}
+
+#[derive(Serialize)]
+pub struct Metrics {
+    /// Operations per second, including dequeues which found the queue empty
+    pub throughput: f64,
+    /// Items enqueued and dequeued per second, which leaves out empty dequeues as they can be much
+    /// faster than real ones, and counts every item of a batch
+    pub effective_throughput: f64,
+    /// Operations, except for the items
+    #[serde(flatten)]
+    pub counts: Counts,
+    pub dequeues: usize,
+    /// Items drained from the queue after the run, which only differs from the prefill plus the
+    /// enqueued items minus the dequeued items if items were lost or duplicated
+    pub final_queue_size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enqueue_latency: Option<LatencySummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dequeue_latency: Option<LatencySummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub perf: Option<BTreeMap<&'static str, PerfMetric>>,
+    pub memory: MemoryUsage,
+}
+
+/// A perf event summed over all threads.
+#[derive(Serialize)]
+pub struct PerfMetric {
+    pub total: u64,
+    /// The total divided by all enqueues and dequeues
+    pub per_operation: f64,
+}
+
+impl<'c> Record<'c> {
+    pub fn new(
+        config: &'c BenchConfig,
+        queue: QueueConfig,
+        Run {
+            threads,
+            memory,
+            final_queue_size,
+        }: Run,
+    ) -> Self {
+        let mut counts = Counts::default();
+        let mut latencies: Option<Latencies> = None;
+        let mut perf: Option<BTreeMap<&'static str, u64>> = None;
+        for thread in &threads {
+            for (&event, &count) in thread.perf.iter().flatten() {
+                *perf
+                    .get_or_insert_with(BTreeMap::new)
+                    .entry(event)
+                    .or_default() += count;
+            }
+            counts.add(&thread.counts);
+            if let Some(thread_latencies) = &thread.latencies {
+                latencies
+                    .get_or_insert_with(Latencies::default)
+                    .merge(thread_latencies);
+            }
+        }
+
+        let duration = config.duration() as f64;
+        let operations = (counts.enqueues + counts.dequeues()).max(1) as f64;
+        let metrics = Metrics {
+            throughput: (counts.enqueues + counts.dequeues()) as f64 / duration,
+            effective_throughput: (counts.enqueued_items + counts.dequeued_items) as f64 / duration,
+            counts,
+            dequeues: counts.dequeues(),
+            final_queue_size,
+            enqueue_latency: latencies.as_ref().map(|l| l.enqueue.summary()),
+            dequeue_latency: latencies.as_ref().map(|l| l.dequeue.summary()),
+            perf: perf.map(|perf| {
+                perf.into_iter()
+                    .map(|(event, total)| {
+                        let per_operation = total as f64 / operations;
+                        (
+                            event,
+                            PerfMetric {
+                                total,
+                                per_operation,
+                            },
+                        )
+                    })
+                    .collect()
+            }),
+            memory,
+        };
+        Self {
+            scenario: None,
+            config,
+            queue,
+            metrics,
+            threads,
+        }
+    }
+}
+
+/// A result which [`Output`] can print.
+pub trait Report: Serialize {
+    /// Prints the result in a human readable form.
+    fn write_text(&self);
+}
+
+/// Prints records in the chosen format.
+pub struct Output {
+    format: OutputFormat,
+    /// The CSV columns, decided by the first record, so all records should be of the same type
+    csv_columns: Option<Vec<String>>,
+}
+
+impl Output {
+    pub fn new(format: OutputFormat) -> Self {
+        Self {
+            format,
+            csv_columns: None,
+        }
+    }
+
+    pub fn write(&mut self, record: &impl Report) {
+        match self.format {
+            OutputFormat::Text => record.write_text(),
+            OutputFormat::Json => println!(
+                "{}",
+                serde_json::to_string(record).expect("records serialize to JSON")
+            ),
+            OutputFormat::Csv => self.write_csv(record),
+        }
+    }
+
+    fn write_csv(&mut self, record: &impl Report) {
+        let value = serde_json::to_value(record).expect("records serialize to JSON");
+        let mut cells = Vec::new();
+        flatten(String::new(), &value, &mut cells);
+        let columns = self.csv_columns.get_or_insert_with(|| {
+            let columns: Vec<_> = cells.iter().map(|(column, _)| column.clone()).collect();
+            println!("{}", csv_row(columns.iter().map(String::as_str)));
+            columns
+        });
+        let row = columns.iter().map(|column| {
+            cells
+                .iter()
+                .find(|(name, _)| name == column)
+                .map_or("", |(_, cell)| cell.as_str())
+        });
+        println!("{}", csv_row(row));
+    }
+}
+
+impl Report for Record<'_> {
+    fn write_text(&self) {
+        if let Some(scenario) = &self.scenario {
+            println!("scenario: {scenario}");
+        }
+        let metrics = &self.metrics;
+        println!("throughput (operations/s): {}", metrics.throughput);
+        println!(
+            "effective throughput (items/s): {}",
+            metrics.effective_throughput
+        );
+        println!("number of enqueues: {}", metrics.counts.enqueues);
+        println!(
+            "number of enqueued items: {}",
+            metrics.counts.enqueued_items
+        );
+        println!("number of dequeues: {}", metrics.dequeues);
+        println!(
+            "number of dequeued items: {}",
+            metrics.counts.dequeued_items
+        );
+        println!(
+            "number of successful dequeues: {}",
+            metrics.counts.successful_dequeues
+        );
+        println!(
+            "number of empty dequeues: {}",
+            metrics.counts.empty_dequeues
+        );
+        println!(
+            "number of retried dequeues: {}",
+            metrics.counts.retried_dequeues
+        );
+        println!("final queue size: {}", metrics.final_queue_size);
+        if let Some(latency) = &metrics.enqueue_latency {
+            println!("enqueue latency (ns): {latency}");
+        }
+        if let Some(latency) = &metrics.dequeue_latency {
+            println!("dequeue latency (ns): {latency}");
+        }
+        let memory = &metrics.memory;
+        println!("memory before: {}", memory.before);
+        println!("memory peak: {}", memory.peak);
+        println!("memory steady: {}", memory.steady);
+        println!("memory after: {}", memory.after);
+        for (event, metric) in metrics.perf.iter().flatten() {
+            println!(
+                "{event}: {} ({:.2} per operation)",
+                metric.total, metric.per_operation
+            );
+        }
+    }
+}
+
+/// Flattens a JSON value into named cells, naming nested fields by their path separated by `.`.
+/// Arrays of objects become one cell per field, with the values of all elements joined by `;`.
+fn flatten(name: String, value: &Value, cells: &mut Vec<(String, String)>) {
+    let field = |field: &str| {
+        if name.is_empty() {
+            field.to_string()
+        } else {
+            format!("{name}.{field}")
+        }
+    };
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                flatten(field(key), value, cells);
+            }
+        }
+        Value::Array(items) => {
+            let mut columns: Vec<(String, Vec<String>)> = Vec::new();
+            for item in items {
+                let mut item_cells = Vec::new();
+                flatten(String::new(), item, &mut item_cells);
+                for (column, cell) in item_cells {
+                    match columns.iter_mut().find(|(name, _)| *name == column) {
+                        Some((_, values)) => values.push(cell),
+                        None => columns.push((column, vec![cell])),
+                    }
+                }
+            }
+            for (column, values) in columns {
+                let name = if column.is_empty() {
+                    name.clone()
+                } else {
+                    field(&column)
+                };
+                cells.push((name, values.join(";")));
+            }
+        }
+        Value::String(string) => cells.push((name, string.clone())),
+        Value::Null => cells.push((name, String::new())),
+        value => cells.push((name, value.to_string())),
+    }
+}
+
+fn csv_row<'a>(cells: impl Iterator<Item = &'a str>) -> String {
+    cells
+        .map(|cell| {
+            if cell.contains([',', '"', '\n']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{csv_row, flatten};
+
+    #[test]
+    fn flattens_nested_fields_and_arrays() {
+        let value = json!({
+            "config": { "prefill": 10, "latency": false },
+            "name": "ms-queue",
+            "threads": [
+                { "workload": "enqueue", "enqueues": 5 },
+                { "workload": "dequeue", "enqueues": 0 },
+            ],
+        });
+        let mut cells = Vec::new();
+        flatten(String::new(), &value, &mut cells);
+        let cells: Vec<_> = cells
+            .iter()
+            .map(|(name, cell)| (name.as_str(), cell.as_str()))
+            .collect();
+        assert_eq!(
+            cells,
+            [
+                ("config.prefill", "10"),
+                ("config.latency", "false"),
+                ("name", "ms-queue"),
+                ("threads.workload", "enqueue;dequeue"),
+                ("threads.enqueues", "5;0"),
+            ]
+        );
+    }
+
+    #[test]
+    fn quotes_csv_cells() {
+        assert_eq!(
+            csv_row(["a", "b,c", "say \"hi\""].into_iter()),
+            "a,\"b,c\",\"say \"\"hi\"\"\""
+        );
+    }
+}
@@ -0,0 +1,74 @@
+//! The items the benchmark threads enqueue.
+
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+/// The sizes in bytes supported by inline payloads, as each needs its own array type.
+pub const INLINE_SIZES: [usize; 5] = [8, 32, 64, 256, 1024];
+
+#[derive(Args, Serialize, Clone)]
+pub struct PayloadConfig {
+    /// the type of the enqueued items.
+    #[arg(long, value_enum, default_value_t = PayloadKind::I32)]
+    pub payload: PayloadKind,
+
+    /// size in bytes of inline, boxed and vec payloads. Inline payloads
+    /// support 8, 32, 64, 256 and 1024 bytes.
+    #[arg(long, default_value_t = 64)]
+    pub payload_size: usize,
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum PayloadKind {
+    /// A plain i32
+    I32,
+    /// A byte array stored in the queue nodes
+    Inline,
+    /// A heap-allocated boxed byte slice
+    Boxed,
+    /// A heap-allocated byte vector
+    Vec,
+}
+
+impl PayloadConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.payload {
+            PayloadKind::Inline if !INLINE_SIZES.contains(&self.payload_size) => Err(format!(
+                "inline payloads can only be {INLINE_SIZES:?} bytes, not {}",
+                self.payload_size
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// An item the benchmark can create and pass through the queues.
+pub trait Payload: Send + Sync + 'static {
+    /// Creates an item of `size` bytes, for payloads where the size is not fixed by the type.
+    fn new(value: usize, size: usize) -> Self;
+}
+
+impl Payload for i32 {
+    fn new(value: usize, _size: usize) -> Self {
+        value as i32
+    }
+}
+
+impl<const N: usize> Payload for [u8; N] {
+    fn new(value: usize, _size: usize) -> Self {
+        [value as u8; N]
+    }
+}
+
+impl Payload for Box<[u8]> {
+    fn new(value: usize, size: usize) -> Self {
+        vec![value as u8; size].into_boxed_slice()
+    }
+}
+
+impl Payload for Vec<u8> {
+    fn new(value: usize, size: usize) -> Self {
+        vec![value as u8; size]
+    }
+}
@@ -0,0 +1,235 @@
+//! Per-thread hardware and software event counters from Linux `perf_event_open`.
+
+use std::{collections::BTreeMap, io};
+
+/// The events to count, each with a software event to count instead if the hardware one can not
+/// be opened, for example in virtual machines.
+const EVENTS: [(Event, Option<Event>); 4] = [
+    (Event::CYCLES, Some(Event::TASK_CLOCK)),
+    (Event::INSTRUCTIONS, None),
+    (Event::CACHE_MISSES, Some(Event::PAGE_FAULTS)),
+    (Event::CONTEXT_SWITCHES, None),
+];
+
+#[derive(Clone, Copy)]
+struct Event {
+    name: &'static str,
+    kind: u32,
+    config: u64,
+}
+
+impl Event {
+    const HARDWARE: u32 = 0;
+    const SOFTWARE: u32 = 1;
+
+    const CYCLES: Event = Event::new("cycles", Self::HARDWARE, 0);
+    const INSTRUCTIONS: Event = Event::new("instructions", Self::HARDWARE, 1);
+    const CACHE_MISSES: Event = Event::new("cache_misses", Self::HARDWARE, 3);
+    const TASK_CLOCK: Event = Event::new("task_clock_ns", Self::SOFTWARE, 1);
+    const PAGE_FAULTS: Event = Event::new("page_faults", Self::SOFTWARE, 2);
+    const CONTEXT_SWITCHES: Event = Event::new("context_switches", Self::SOFTWARE, 3);
+
+    const fn new(name: &'static str, kind: u32, config: u64) -> Self {
+        Self { name, kind, config }
+    }
+
+    /// Software events such as context switches happen in the kernel, so excluding it would
+    /// leave them at zero. Hardware events only count the benchmark's own code.
+    fn exclude_kernel(&self) -> bool {
+        self.kind == Self::HARDWARE
+    }
+}
+
+/// Counters of the thread which opened them, which only count while enabled.
+pub struct Counters {
+    counters: Vec<(&'static str, sys::Counter)>,
+}
+
+impl Counters {
+    /// Opens every event which is available, falling back to software events where possible.
+    pub fn open() -> Self {
+        let counters = EVENTS
+            .iter()
+            .filter_map(|(event, fallback)| {
+                let open = |event: &Event| {
+                    sys::Counter::open(event.kind, event.config, event.exclude_kernel())
+                        .map(|counter| (event.name, counter))
+                };
+                open(event).or_else(|| fallback.as_ref().and_then(open))
+            })
+            .collect();
+        Self { counters }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counters.is_empty()
+    }
+
+    pub fn enable(&self) -> io::Result<()> {
+        self.counters
+            .iter()
+            .try_for_each(|(_, counter)| counter.enable())
+    }
+
+    pub fn disable(&self) -> io::Result<()> {
+        self.counters
+            .iter()
+            .try_for_each(|(_, counter)| counter.disable())
+    }
+
+    pub fn read(&self) -> BTreeMap<&'static str, u64> {
+        self.counters
+            .iter()
+            .filter_map(|(name, counter)| Some((*name, counter.read()?)))
+            .collect()
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::{
+        fs::File,
+        io::{self, Read},
+        os::fd::{AsRawFd, FromRawFd},
+    };
+
+    const ENABLE: libc::c_ulong = 0x2400;
+    const DISABLE: libc::c_ulong = 0x2401;
+
+    /// The first version of `struct perf_event_attr`, which all kernels accept.
+    #[repr(C)]
+    #[derive(Default)]
+    struct Attributes {
+        kind: u32,
+        size: u32,
+        config: u64,
+        sample_period: u64,
+        sample_type: u64,
+        read_format: u64,
+        flags: u64,
+        wakeup_events: u32,
+        bp_type: u32,
+        config1: u64,
+    }
+
+    const DISABLED: u64 = 1 << 0;
+    const EXCLUDE_KERNEL: u64 = 1 << 5;
+    const EXCLUDE_HV: u64 = 1 << 6;
+
+    pub struct Counter(File);
+
+    impl Counter {
+        /// Fails for events which count the kernel if `perf_event_paranoid` only lets the user
+        /// count their own code.
+        pub fn open(kind: u32, config: u64, exclude_kernel: bool) -> Option<Self> {
+            let mut flags = DISABLED | EXCLUDE_HV;
+            if exclude_kernel {
+                flags |= EXCLUDE_KERNEL;
+            }
+            let attributes = Attributes {
+                kind,
+                size: std::mem::size_of::<Attributes>() as u32,
+                config,
+                flags,
+                ..Default::default()
+            };
+            // SAFETY: the attributes outlive the call, and their size field matches their layout.
+            // The arguments count this thread on any CPU
+            let fd = unsafe {
+                libc::syscall(
+                    libc::SYS_perf_event_open,
+                    &attributes as *const Attributes,
+                    0,
+                    -1,
+                    -1,
+                    0,
+                )
+            };
+            // SAFETY: a non-negative return value is a new file descriptor that we own
+            (fd >= 0).then(|| Counter(unsafe { File::from_raw_fd(fd as i32) }))
+        }
+
+        pub fn enable(&self) -> io::Result<()> {
+            self.ioctl(ENABLE)
+        }
+
+        pub fn disable(&self) -> io::Result<()> {
+            self.ioctl(DISABLE)
+        }
+
+        fn ioctl(&self, request: libc::c_ulong) -> io::Result<()> {
+            // SAFETY: the file descriptor is open for as long as `self`, and the enable and
+            // disable requests take no pointer, only a flags argument
+            let result = unsafe { libc::ioctl(self.0.as_raw_fd(), request, 0) };
+            if result == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        pub fn read(&self) -> Option<u64> {
+            let mut value = [0; 8];
+            (&self.0).read_exact(&mut value).ok()?;
+            Some(u64::from_ne_bytes(value))
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::io;
+
+    pub struct Counter;
+
+    impl Counter {
+        pub fn open(_kind: u32, _config: u64, _exclude_kernel: bool) -> Option<Self> {
+            None
+        }
+
+        pub fn enable(&self) -> io::Result<()> {
+            Ok(())
+        }
+
+        pub fn disable(&self) -> io::Result<()> {
+            Ok(())
+        }
+
+        pub fn read(&self) -> Option<u64> {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Counters;
+
+    #[test]
+    fn counts_only_while_enabled() {
+        let counters = Counters::open();
+        let before = counters.read();
+        assert!(before.values().all(|&value| value == 0));
+
+        counters.enable().unwrap();
+        let mut sum = 0u64;
+        for i in 0..1_000_000 {
+            sum = std::hint::black_box(sum + i);
+        }
+        // Sleeping switches to another task at least once
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        counters.disable().unwrap();
+        let after = counters.read();
+        assert_eq!(after, counters.read());
+        // Not every environment allows perf events, but those we get should have counted
+        for event in [
+            "cycles",
+            "instructions",
+            "task_clock_ns",
+            "context_switches",
+        ] {
+            if let Some(&value) = after.get(event) {
+                assert!(value > 0, "{event} did not count");
+            }
+        }
+    }
+}
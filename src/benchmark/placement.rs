@@ -0,0 +1,171 @@
+//! Which CPUs the worker threads are pinned to.
+
+use std::fs;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Placement {
+    /// Fill all hyperthreads of a core, then all cores of a socket, before the
+    /// next socket
+    Compact,
+    /// Alternate between sockets, and use every core once before any
+    /// hyperthread sibling
+    Scatter,
+    /// Only use one hyperthread of every core, filling a socket first
+    NoSmt,
+    /// Put producers and consumers on separate sockets, or on separate halves
+    /// of the CPUs with a single socket
+    SplitSockets,
+    /// Let the operating system schedule the threads
+    None,
+}
+
+/// A CPU the process may run on, and where it is in the machine.
+#[derive(Clone, Copy, Debug)]
+pub struct Cpu {
+    pub id: usize,
+    pub package: usize,
+    /// Only unique within the package
+    pub core: usize,
+}
+
+/// The CPUs the process may run on, with their topology read from sysfs. CPUs whose topology is
+/// unknown are treated as separate cores of a single socket.
+pub fn topology() -> Vec<Cpu> {
+    let read = |id: usize, file: &str| {
+        fs::read_to_string(format!("/sys/devices/system/cpu/cpu{id}/topology/{file}"))
+            .ok()
+            .and_then(|text| text.trim().parse().ok())
+    };
+    core_affinity::get_core_ids()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|core| Cpu {
+            id: core.id,
+            package: read(core.id, "physical_package_id").unwrap_or(0),
+            core: read(core.id, "core_id").unwrap_or(core.id),
+        })
+        .collect()
+}
+
+impl Placement {
+    /// The CPU of every worker, where the first `producers` of the `workers` are producers.
+    /// Workers are not pinned with [`Placement::None`] or if no CPUs are known, and CPUs are
+    /// reused when there are more workers than CPUs.
+    pub fn cpus(self, cpus: &[Cpu], workers: usize, producers: usize) -> Vec<Option<usize>> {
+        if self == Placement::None || cpus.is_empty() {
+            return vec![None; workers];
+        }
+        let mut compact = cpus.to_vec();
+        compact.sort_by_key(|cpu| (cpu.package, cpu.core, cpu.id));
+        // How many hyperthreads of the same core come before every CPU
+        let sibling = |cpu: &Cpu| {
+            compact
+                .iter()
+                .filter(|other| {
+                    (other.package, other.core) == (cpu.package, cpu.core) && other.id < cpu.id
+                })
+                .count()
+        };
+
+        let order: Vec<Cpu> = match self {
+            Placement::Compact => compact,
+            Placement::Scatter => {
+                let mut order = compact.clone();
+                order.sort_by_key(|cpu| (sibling(cpu), cpu.core, cpu.package, cpu.id));
+                order
+            }
+            Placement::NoSmt => compact
+                .iter()
+                .copied()
+                .filter(|cpu| sibling(cpu) == 0)
+                .collect(),
+            Placement::SplitSockets => {
+                let mut packages: Vec<_> = compact.iter().map(|cpu| cpu.package).collect();
+                packages.dedup();
+                let (producer_cpus, consumer_cpus): (Vec<_>, Vec<_>) = if packages.len() > 1 {
+                    let producer_packages = &packages[..packages.len() / 2];
+                    compact
+                        .iter()
+                        .partition(|cpu| producer_packages.contains(&cpu.package))
+                } else {
+                    let (first, second) = compact.split_at(compact.len().div_ceil(2));
+                    (first.iter().collect(), second.iter().collect())
+                };
+                let consumer_cpus = if consumer_cpus.is_empty() {
+                    &producer_cpus
+                } else {
+                    &consumer_cpus
+                };
+                let producers = producers.min(workers);
+                return producer_cpus
+                    .iter()
+                    .cycle()
+                    .take(producers)
+                    .chain(consumer_cpus.iter().cycle().take(workers - producers))
+                    .map(|cpu| Some(cpu.id))
+                    .collect();
+            }
+            Placement::None => unreachable!(),
+        };
+        order
+            .iter()
+            .cycle()
+            .take(workers)
+            .map(|cpu| Some(cpu.id))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Cpu, Placement};
+
+    /// Two sockets with two cores of two hyperthreads each, numbered like Linux does, with the
+    /// second hyperthreads of all cores after the first ones.
+    fn machine() -> Vec<Cpu> {
+        (0..8)
+            .map(|id| Cpu {
+                id,
+                package: id / 2 % 2,
+                core: id % 2,
+            })
+            .collect()
+    }
+
+    fn cpus(placement: Placement, workers: usize, producers: usize) -> Vec<usize> {
+        placement
+            .cpus(&machine(), workers, producers)
+            .into_iter()
+            .map(Option::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn orders_cpus_by_topology() {
+        assert_eq!(cpus(Placement::Compact, 8, 4), [0, 4, 1, 5, 2, 6, 3, 7]);
+        assert_eq!(cpus(Placement::Scatter, 8, 4), [0, 2, 1, 3, 4, 6, 5, 7]);
+        assert_eq!(cpus(Placement::NoSmt, 6, 3), [0, 1, 2, 3, 0, 1]);
+    }
+
+    #[test]
+    fn splits_producers_and_consumers_by_socket() {
+        assert_eq!(cpus(Placement::SplitSockets, 6, 2), [0, 4, 2, 6, 3, 7]);
+
+        let single_socket: Vec<_> = machine()
+            .into_iter()
+            .map(|cpu| Cpu { package: 0, ..cpu })
+            .collect();
+        let split = Placement::SplitSockets.cpus(&single_socket[..4], 4, 2);
+        assert_eq!(split, [Some(0), Some(2), Some(1), Some(3)]);
+    }
+
+    #[test]
+    fn does_not_pin_without_placement() {
+        assert_eq!(Placement::None.cpus(&machine(), 2, 1), [None, None]);
+        assert_eq!(Placement::Compact.cpus(&[], 1, 1), [None]);
+    }
+}
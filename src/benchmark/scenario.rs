@@ -0,0 +1,302 @@
+//! Named benchmark scenarios loaded from a TOML file.
+//!
+//! Every scenario is a `[[scenario]]` table with a `name` and a `queue`, which is any spec the
+//! registry can build. All other fields are
+//! optional and default to the command line options, and the threads and the duration to
+//! [`DEFAULT_THREADS`] and [`DEFAULT_DURATION`] if not given there either:
+//! ```toml
+//! [[scenario]]
+//! name = "dra-mixed"
+//! queue = "dra(ms,16,2)"
+//! producer_threads = 4
+//! consumer_threads = 4
+//! duration = 2
+//! workload = "mixed"
+//! enqueue_percentage = 70
+//! ```
+
+use std::{collections::HashSet, fs, path::PathBuf};
+
+use clap::Args;
+use relaxed_queues::registry::QueueSpec;
+use serde::Deserialize;
+
+use crate::{run, BenchConfig};
+
+use super::{output::Output, payload::PayloadKind, placement::Placement, workload::Workload};
+
+/// The producer and consumer threads of scenarios which set them nowhere.
+const DEFAULT_THREADS: usize = 1;
+
+/// The duration in seconds of scenarios which set it nowhere.
+const DEFAULT_DURATION: usize = 1;
+
+#[derive(Args, Clone)]
+pub struct ScenarioConfig {
+    /// The TOML file with the scenarios
+    file: PathBuf,
+
+    /// Only run the scenarios with these names
+    #[arg(long, value_delimiter = ',')]
+    only: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScenarioFile {
+    #[serde(default)]
+    scenario: Vec<Scenario>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Scenario {
+    name: String,
+    queue: String,
+    producer_threads: Option<usize>,
+    consumer_threads: Option<usize>,
+    prefill: Option<usize>,
+    duration: Option<usize>,
+    workload: Option<Workload>,
+    enqueue_percentage: Option<u32>,
+    burst_length: Option<usize>,
+    batch_size: Option<u64>,
+    payload: Option<PayloadKind>,
+    payload_size: Option<usize>,
+    work_ns: Option<u64>,
+    random_work: Option<bool>,
+    placement: Option<Placement>,
+}
+
+impl Scenario {
+    /// The configuration of the scenario, with unset fields taken from `defaults`.
+    fn resolve(&self, defaults: &BenchConfig) -> Result<(BenchConfig, QueueSpec), String> {
+        let queue: QueueSpec = self
+            .queue
+            .parse()
+            .map_err(|error| format!("has an invalid queue: {error}"))?;
+        let mut config = defaults.clone();
+        let or_default = |scenario: Option<usize>, command_line: Option<usize>, default| {
+            Some(scenario.or(command_line).unwrap_or(default))
+        };
+        config.producer_threads = or_default(
+            self.producer_threads,
+            config.producer_threads,
+            DEFAULT_THREADS,
+        );
+        config.consumer_threads = or_default(
+            self.consumer_threads,
+            config.consumer_threads,
+            DEFAULT_THREADS,
+        );
+        config.prefill = self.prefill.unwrap_or(config.prefill);
+        config.duration = or_default(self.duration, config.duration, DEFAULT_DURATION);
+        let workload = &mut config.workload;
+        workload.workload = self.workload.unwrap_or(workload.workload);
+        workload.enqueue_percentage = self
+            .enqueue_percentage
+            .unwrap_or(workload.enqueue_percentage);
+        workload.burst_length = self.burst_length.unwrap_or(workload.burst_length);
+        workload.batch_size = self.batch_size.unwrap_or(workload.batch_size);
+        let payload = &mut config.payload;
+        payload.payload = self.payload.unwrap_or(payload.payload);
+        payload.payload_size = self.payload_size.unwrap_or(payload.payload_size);
+        config.work.work_ns = self.work_ns.unwrap_or(config.work.work_ns);
+        config.work.random_work = self.random_work.unwrap_or(config.work.random_work);
+        config.placement = self.placement.unwrap_or(config.placement);
+
+        if config.duration() == 0 {
+            return Err("needs a duration of at least one second".to_string());
+        }
+        if config.workload.enqueue_percentage > 100 {
+            return Err("enqueue_percentage can be at most 100".to_string());
+        }
+        if config.workload.batch_size == 0 {
+            return Err("batch_size should be at least 1".to_string());
+        }
+        let (producers, consumers) = config.threads();
+        if producers + consumers == 0 {
+            return Err("needs at least one thread".to_string());
+        }
+        config.payload.validate()?;
+        Ok((config, queue))
+    }
+}
+
+/// Parses and validates all scenarios, returning every problem found instead of the first one.
+fn parse(
+    text: &str,
+    defaults: &BenchConfig,
+    only: &[String],
+) -> Result<Vec<(String, BenchConfig, QueueSpec)>, String> {
+    let file: ScenarioFile = toml::from_str(text).map_err(|error| error.to_string())?;
+    let mut errors = Vec::new();
+    let mut names = HashSet::new();
+    let mut scenarios = Vec::new();
+    for scenario in &file.scenario {
+        if !names.insert(scenario.name.as_str()) {
+            errors.push(format!("scenario {} is defined twice", scenario.name));
+        }
+        if !only.is_empty() && !only.contains(&scenario.name) {
+            continue;
+        }
+        match scenario.resolve(defaults) {
+            Ok((config, queue)) => scenarios.push((scenario.name.clone(), config, queue)),
+            Err(error) => errors.push(format!("scenario {} {error}", scenario.name)),
+        }
+    }
+    for name in only {
+        if !names.contains(name.as_str()) {
+            errors.push(format!("no scenario named {name}"));
+        }
+    }
+    if errors.is_empty() {
+        Ok(scenarios)
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+impl ScenarioConfig {
+    /// Runs all scenarios of the file in order, after checking that all of them are valid.
+    pub fn run(&self, config: &BenchConfig, output: &mut Output) -> Result<(), String> {
+        let text = fs::read_to_string(&self.file)
+            .map_err(|error| format!("could not read {}: {error}", self.file.display()))?;
+        for (name, config, queue) in parse(&text, config, &self.only)? {
+            let mut record = run(queue, &config);
+            record.scenario = Some(name);
+            output.write(&record);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use relaxed_queues::registry::{QueueSpec, StrictKind};
+
+    use crate::{BenchConfig, Command};
+
+    use super::parse;
+
+    fn defaults() -> BenchConfig {
+        BenchConfig::parse_from(["bench", "-p", "2", "-d", "3", "queue", "ms"])
+    }
+
+    #[test]
+    fn fills_in_defaults() {
+        let text = r#"
+            [[scenario]]
+            name = "dra"
+            queue = "dra-queue(crossbeam-queue,8)"
+            consumer_threads = 4
+            workload = "pairs"
+            batch_size = 16
+
+            [[scenario]]
+            name = "ms"
+            queue = "ms-queue"
+            duration = 1
+        "#;
+        let scenarios = parse(text, &defaults(), &[]).unwrap();
+        assert_eq!(scenarios.len(), 2);
+
+        let (name, config, queue) = &scenarios[0];
+        assert_eq!(name, "dra");
+        assert_eq!(queue.to_string(), "dra(crossbeam,8,2)");
+        assert_eq!(config.threads(), (2, 4));
+        assert_eq!(config.duration(), 3);
+        assert_eq!(config.workload.batch_size, 16);
+
+        let (name, config, queue) = &scenarios[1];
+        assert_eq!(name, "ms");
+        assert_eq!(*queue, QueueSpec::Strict(StrictKind::MSQueue));
+        assert_eq!(config.threads(), (2, 1));
+        assert_eq!(config.duration(), 1);
+        assert_eq!(config.workload.batch_size, 1);
+    }
+
+    #[test]
+    fn reports_all_invalid_scenarios() {
+        let text = r#"
+            [[scenario]]
+            name = "no-subqueue"
+            queue = "round-robin"
+
+            [[scenario]]
+            name = "rr-with-d"
+            queue = "rr(ms,4,2)"
+
+            [[scenario]]
+            name = "no-subqueue"
+            queue = "ms-queue"
+            duration = 0
+        "#;
+        let errors = parse(text, &defaults(), &[])
+            .err()
+            .expect("scenarios are invalid");
+        assert_eq!(errors.lines().count(), 4, "{errors}");
+        assert!(errors.contains("scenario no-subqueue is defined twice"));
+        assert!(errors.contains("scenario rr-with-d has an invalid queue: rr takes"));
+    }
+
+    #[test]
+    fn selects_scenarios_by_name() {
+        let text = r#"
+            [[scenario]]
+            name = "first"
+            queue = "ms-queue"
+
+            [[scenario]]
+            name = "second"
+            queue = "crossbeam-queue"
+        "#;
+        let scenarios = parse(text, &defaults(), &["second".to_string()]).unwrap();
+        assert_eq!(scenarios.len(), 1);
+        assert_eq!(scenarios[0].0, "second");
+
+        let error = parse(text, &defaults(), &["third".to_string()])
+            .err()
+            .unwrap();
+        assert_eq!(error, "no scenario named third");
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let text = r#"
+            [[scenario]]
+            name = "typo"
+            queue = "ms-queue"
+            producers = 4
+        "#;
+        assert!(parse(text, &defaults(), &[]).is_err());
+    }
+
+    #[test]
+    fn runs_the_documented_command() {
+        // As documented in scenarios.toml, which sets the durations itself
+        let config =
+            BenchConfig::try_parse_from(["relaxed-queues", "scenarios", "scenarios.toml"]).unwrap();
+        let Command::Scenarios(scenarios) = &config.command else {
+            panic!("should parse the scenarios command");
+        };
+        assert_eq!(scenarios.file.to_str(), Some("scenarios.toml"));
+        assert!(config.check_required().is_ok());
+
+        let text = include_str!("../../scenarios.toml");
+        assert!(parse(text, &config, &[]).unwrap().len() > 1);
+    }
+
+    #[test]
+    fn requires_threads_and_duration_for_a_single_queue() {
+        let config = BenchConfig::parse_from(["bench", "-p", "2", "-c", "2", "queue", "ms"]);
+        assert!(config.check_required().is_err());
+        assert!(defaults().check_required().is_err());
+
+        let config =
+            BenchConfig::parse_from(["bench", "-p", "2", "-c", "2", "-d", "1", "queue", "ms"]);
+        assert!(config.check_required().is_ok());
+    }
+}
@@ -0,0 +1,336 @@
+//! Runs the benchmark over many configurations and summarizes repeated runs.
+
+use std::str::FromStr;
+
+use clap::Args;
+use relaxed_queues::registry::{
+    QueueSpec, RelaxedKind, RelaxedSpec, StrictKind, SubQueueSpec, DEFAULT_D,
+};
+use serde::Serialize;
+
+use crate::{run, BenchConfig, QueueConfig};
+
+use super::output::{Metrics, Output, Report};
+
+#[derive(Args, Clone)]
+pub struct SweepConfig {
+    /// The queues to benchmark, where relaxed designs are built over every
+    /// combination of the sub-queue parameters below
+    #[arg(long, value_delimiter = ',', required_unless_present = "specs")]
+    queues: Vec<SweptQueue>,
+
+    /// Further queues to benchmark, described like dra(ms,16,2) and separated
+    /// by `;`
+    #[arg(long, value_delimiter = ';')]
+    specs: Vec<QueueSpec>,
+
+    /// The thread counts to benchmark. Every point runs this many producer and
+    /// consumer threads
+    #[arg(long, value_delimiter = ',', required = true)]
+    threads: Vec<usize>,
+
+    /// The sub-queues relaxed queues are built from
+    #[arg(long, value_delimiter = ',', default_value = "ms-queue")]
+    subqueue: Vec<StrictKind>,
+
+    /// The numbers of sub-queues of relaxed queues
+    #[arg(short, long, value_delimiter = ',', default_value = "8")]
+    subqueues: Vec<usize>,
+
+    /// The numbers of sub-structures the d-RA and DCBO queues sample in every
+    /// operation
+    #[arg(short = 'c', long, value_delimiter = ',', default_value = "2")]
+    choice: Vec<usize>,
+
+    /// The numbers of items every operation enqueues or dequeues
+    #[arg(long, value_delimiter = ',', default_value = "1", value_parser = clap::value_parser!(u64).range(1..))]
+    batch_sizes: Vec<u64>,
+
+    /// How many measured runs to do of every configuration
+    #[arg(short, long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+    repetitions: u32,
+
+    /// Duration in seconds of an unmeasured run before the repetitions of every
+    /// configuration
+    #[arg(short, long, default_value_t = 1)]
+    warmup: usize,
+}
+
+/// A strict queue, or a relaxed design to build over every combination of sub-queue parameters.
+#[derive(Clone, Copy)]
+enum SweptQueue {
+    Strict(StrictKind),
+    Relaxed(RelaxedKind),
+}
+
+impl FromStr for SweptQueue {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        name.parse()
+            .map(SweptQueue::Strict)
+            .or_else(|_| name.parse().map(SweptQueue::Relaxed))
+            .map_err(|_| format!("unknown queue {name}"))
+    }
+}
+
+impl SweepConfig {
+    /// All queue configurations to benchmark.
+    fn queues(&self) -> Vec<QueueSpec> {
+        let mut queues = Vec::new();
+        for &queue in &self.queues {
+            let kind = match queue {
+                SweptQueue::Strict(kind) => {
+                    queues.push(QueueSpec::Strict(kind));
+                    continue;
+                }
+                SweptQueue::Relaxed(kind) => kind,
+            };
+            // Designs without a d would otherwise be built once for every d
+            let choices = if kind.takes_d() {
+                &self.choice[..]
+            } else {
+                &[DEFAULT_D]
+            };
+            for &subqueue in &self.subqueue {
+                for &subqueues in &self.subqueues {
+                    queues.extend(choices.iter().map(|&d| {
+                        QueueSpec::Relaxed(RelaxedSpec::new(
+                            kind,
+                            SubQueueSpec::Strict(subqueue),
+                            subqueues,
+                            d,
+                        ))
+                    }));
+                }
+            }
+        }
+        queues.extend(&self.specs);
+        queues
+    }
+
+    /// Benchmarks every combination of queue, thread count and batch size, writing one summary per
+    /// point.
+    pub fn run(&self, config: &BenchConfig, output: &mut Output) {
+        let points = self.threads.iter().flat_map(|&threads| {
+            self.batch_sizes
+                .iter()
+                .map(move |&batch_size| (threads, batch_size))
+        });
+        for queue in self.queues() {
+            for (threads, batch_size) in points.clone() {
+                let mut config = config.clone();
+                config.producer_threads = Some(threads);
+                config.consumer_threads = Some(threads);
+                config.workload.batch_size = batch_size;
+                if self.warmup > 0 {
+                    let warmup = BenchConfig {
+                        duration: Some(self.warmup),
+                        ..config.clone()
+                    };
+                    run(queue, &warmup);
+                }
+
+                let runs: Vec<_> = (0..self.repetitions)
+                    .map(|_| run(queue, &config).metrics)
+                    .collect();
+                let statistics = |metric: fn(&Metrics) -> f64| {
+                    Statistics::new(&runs.iter().map(metric).collect::<Vec<_>>())
+                };
+                // Only summarized if every run measured it
+                let optional_statistics = |metric: fn(&Metrics) -> Option<u64>| {
+                    let samples: Option<Vec<_>> = runs
+                        .iter()
+                        .map(|m| metric(m).map(|value| value as f64))
+                        .collect();
+                    samples.map(|samples| Statistics::new(&samples))
+                };
+                output.write(&SweepPoint {
+                    config: &config,
+                    queue: QueueConfig::new(queue),
+                    repetitions: self.repetitions,
+                    throughput: statistics(|m| m.throughput),
+                    effective_throughput: statistics(|m| m.effective_throughput),
+                    enqueues: statistics(|m| m.counts.enqueues as f64),
+                    dequeues: statistics(|m| m.dequeues as f64),
+                    peak_allocated: optional_statistics(|m| m.memory.peak.allocated),
+                    steady_allocated: optional_statistics(|m| m.memory.steady.allocated),
+                });
+            }
+        }
+    }
+}
+
+/// The summarized repetitions of a single configuration.
+#[derive(Serialize)]
+struct SweepPoint<'c> {
+    config: &'c BenchConfig,
+    queue: QueueConfig,
+    repetitions: u32,
+    throughput: Statistics,
+    effective_throughput: Statistics,
+    enqueues: Statistics,
+    dequeues: Statistics,
+    /// Bytes allocated at the peak of every run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peak_allocated: Option<Statistics>,
+    /// Bytes allocated in the second half of every run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    steady_allocated: Option<Statistics>,
+}
+
+impl Report for SweepPoint<'_> {
+    fn write_text(&self) {
+        let queue = &self.queue;
+        print!("{}", queue.name);
+        if let (Some(subqueue), Some(subqueues)) = (&queue.subqueue, queue.subqueues) {
+            print!(" over {subqueues} {subqueue}");
+        }
+        if let Some(d) = queue.d {
+            print!(" with d = {d}");
+        }
+        let (producers, consumers) = self.config.threads();
+        println!(
+            ", {producers} producers and {consumers} consumers, batch size {}, {} repetitions",
+            self.config.workload.batch_size, self.repetitions
+        );
+        println!("    throughput (operations/s): {}", self.throughput);
+        println!(
+            "    effective throughput (items/s): {}",
+            self.effective_throughput
+        );
+        println!("    enqueues: {}", self.enqueues);
+        println!("    dequeues: {}", self.dequeues);
+        if let Some(peak) = &self.peak_allocated {
+            println!("    peak allocated bytes: {peak}");
+        }
+        if let Some(steady) = &self.steady_allocated {
+            println!("    steady allocated bytes: {steady}");
+        }
+    }
+}
+
+/// The mean of some samples, with a 95% confidence interval from the t-distribution.
+#[derive(Serialize, Debug)]
+struct Statistics {
+    mean: f64,
+    std_dev: f64,
+    ci95_low: f64,
+    ci95_high: f64,
+}
+
+impl Statistics {
+    fn new(samples: &[f64]) -> Self {
+        let n = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / n;
+        let (std_dev, half_width) = if samples.len() > 1 {
+            let variance = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0);
+            let std_dev = variance.sqrt();
+            (std_dev, t_critical(samples.len() - 1) * std_dev / n.sqrt())
+        } else {
+            (0.0, 0.0)
+        };
+        Self {
+            mean,
+            std_dev,
+            ci95_low: mean - half_width,
+            ci95_high: mean + half_width,
+        }
+    }
+}
+
+impl std::fmt::Display for Statistics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "mean: {:.0}, std dev: {:.0}, 95% ci: [{:.0}, {:.0}]",
+            self.mean, self.std_dev, self.ci95_low, self.ci95_high
+        )
+    }
+}
+
+/// The two-sided 95% critical value of the t-distribution with the given degrees of freedom.
+fn t_critical(degrees_of_freedom: usize) -> f64 {
+    const TABLE: [f64; 30] = [
+        12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+        2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+        2.052, 2.048, 2.045, 2.042,
+    ];
+    match degrees_of_freedom {
+        0 => f64::INFINITY,
+        df if df <= TABLE.len() => TABLE[df - 1],
+        // Close enough to the normal distribution
+        _ => 1.960,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use crate::{BenchConfig, Command};
+
+    use super::Statistics;
+
+    #[test]
+    fn builds_relaxed_queues_over_every_combination() {
+        let config = BenchConfig::parse_from([
+            "bench",
+            "sweep",
+            "--queues",
+            "ms-queue,dra,round-robin",
+            "--specs",
+            "rr(dra(ms,8,2),2)",
+            "--threads",
+            "1",
+            "--subqueues",
+            "4,8",
+            "--choice",
+            "2,3",
+        ]);
+        let Command::Sweep(sweep) = &config.command else {
+            panic!("should parse the sweep command");
+        };
+        let queues: Vec<_> = sweep.queues().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            queues,
+            [
+                "ms",
+                "dra(ms,4,2)",
+                "dra(ms,4,3)",
+                "dra(ms,8,2)",
+                "dra(ms,8,3)",
+                "rr(ms,4)",
+                "rr(ms,8)",
+                "rr(dra(ms,8,2),2)",
+            ]
+        );
+        assert!(BenchConfig::try_parse_from([
+            "bench",
+            "sweep",
+            "--queues",
+            "stack",
+            "--threads",
+            "1"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn statistics_of_samples() {
+        let stats = Statistics::new(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+        assert_eq!(stats.mean, 5.0);
+        assert!((stats.std_dev - 2.138).abs() < 1e-3, "{stats:?}");
+        // t = 2.365 for 7 degrees of freedom
+        let half_width = 2.365 * stats.std_dev / 8f64.sqrt();
+        assert!((stats.ci95_high - 5.0 - half_width).abs() < 1e-9);
+        assert!((5.0 - stats.ci95_low - half_width).abs() < 1e-9);
+    }
+
+    #[test]
+    fn single_sample_has_no_spread() {
+        let stats = Statistics::new(&[3.0]);
+        assert_eq!((stats.mean, stats.std_dev), (3.0, 0.0));
+        assert_eq!((stats.ci95_low, stats.ci95_high), (3.0, 3.0));
+    }
+}
@@ -0,0 +1,105 @@
+//! Simulated work on every item between queue operations.
+
+use std::{
+    hint::black_box,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use clap::Args;
+use rand::{rngs::ThreadRng, Rng};
+use serde::Serialize;
+
+#[derive(Args, Serialize, Clone)]
+pub struct WorkConfig {
+    /// nanoseconds of busy-waiting after every operation, simulating the work
+    /// done on every item.
+    #[arg(long, default_value_t = 0)]
+    pub work_ns: u64,
+
+    /// pick the work after every operation uniformly at random between 0 and
+    /// twice work-ns, instead of always waiting work-ns.
+    #[arg(long)]
+    pub random_work: bool,
+}
+
+/// Busy-waits for a number of nanoseconds, without reading the clock while waiting.
+pub struct Work {
+    nanos: u64,
+    random: bool,
+    spins_per_nano: f64,
+}
+
+impl Work {
+    /// Returns None if there is no work to simulate.
+    pub fn new(config: &WorkConfig) -> Option<Self> {
+        (config.work_ns > 0).then(|| Self {
+            nanos: config.work_ns,
+            random: config.random_work,
+            spins_per_nano: spins_per_nano(),
+        })
+    }
+
+    pub fn run(&self, rng: &mut ThreadRng) {
+        let nanos = if self.random {
+            rng.gen_range(0..=2 * self.nanos)
+        } else {
+            self.nanos
+        };
+        spin((nanos as f64 * self.spins_per_nano) as u64);
+    }
+}
+
+fn spin(iterations: u64) {
+    for i in 0..iterations {
+        black_box(i);
+    }
+}
+
+/// How many iterations of [`spin`] run per nanosecond, measured once per process.
+fn spins_per_nano() -> f64 {
+    static SPINS_PER_NANO: OnceLock<f64> = OnceLock::new();
+    *SPINS_PER_NANO.get_or_init(|| {
+        const ITERATIONS: u64 = 10_000_000;
+        // Warm up the core before measuring
+        spin(ITERATIONS);
+        let start = Instant::now();
+        spin(ITERATIONS);
+        let elapsed = start.elapsed().max(Duration::from_nanos(1));
+        ITERATIONS as f64 / elapsed.as_nanos() as f64
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{Work, WorkConfig};
+
+    #[test]
+    fn waits_roughly_the_requested_time() {
+        let work = Work::new(&WorkConfig {
+            work_ns: 1_000_000,
+            random_work: false,
+        })
+        .unwrap();
+        let mut rng = rand::thread_rng();
+        let start = Instant::now();
+        for _ in 0..10 {
+            work.run(&mut rng);
+        }
+        let elapsed = start.elapsed();
+        // Generous bounds, as the test threads compete for the cores
+        assert!(elapsed >= Duration::from_millis(2), "{elapsed:?}");
+        assert!(elapsed <= Duration::from_millis(500), "{elapsed:?}");
+    }
+
+    #[test]
+    fn no_work_by_default() {
+        assert!(Work::new(&WorkConfig {
+            work_ns: 0,
+            random_work: true,
+        })
+        .is_none());
+    }
+}
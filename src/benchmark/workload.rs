@@ -0,0 +1,337 @@
+//! The operation mixes the benchmark threads can run.
+
+use std::{
+    collections::BTreeMap,
+    hint::black_box,
+    marker::PhantomData,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
+};
+
+use clap::{Args, ValueEnum};
+use rand::{rngs::ThreadRng, Rng};
+use serde::{Deserialize, Serialize};
+
+use relaxed_queues::Handle;
+
+use crate::BenchConfig;
+
+use super::{
+    latency::{Histogram, Latencies},
+    payload::Payload,
+    work::Work,
+};
+
+/// The value every benchmark thread enqueues.
+const ITEM: usize = 405;
+
+#[derive(Args, Serialize, Clone)]
+pub struct WorkloadConfig {
+    /// the operation mix to run. All workloads except producer-consumer run it
+    /// on producer-threads + consumer-threads identical threads.
+    #[arg(long, value_enum, default_value_t = Workload::ProducerConsumer)]
+    pub workload: Workload,
+
+    /// percentage of enqueues in the mixed workload.
+    #[arg(long, default_value_t = 50, value_parser = clap::value_parser!(u32).range(0..=100))]
+    pub enqueue_percentage: u32,
+
+    /// number of operations in every burst of the bursty workload, and the
+    /// number of elements added in every refill of the drain-refill workload.
+    #[arg(long, default_value_t = 1000)]
+    pub burst_length: usize,
+
+    /// number of items every operation enqueues or dequeues, using the batch
+    /// operations of the queue when above one.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    pub batch_size: u64,
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Workload {
+    /// Producer threads only enqueue and consumer threads only dequeue
+    ProducerConsumer,
+    /// Every thread alternates between enqueues and dequeues
+    Pairs,
+    /// Every thread picks each operation at random
+    Mixed,
+    /// Every thread alternates between bursts of enqueues and bursts of dequeues
+    Bursty,
+    /// Every thread dequeues until the queue is empty, then refills it
+    DrainRefill,
+}
+
+/// What a single benchmark thread does.
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(tag = "workload", rename_all = "kebab-case")]
+pub enum ThreadWorkload {
+    Enqueue,
+    Dequeue,
+    Pairs,
+    Mixed { enqueue_percentage: u32 },
+    Bursty { burst_length: usize },
+    DrainRefill { refill: usize },
+}
+
+/// Operation counts of one or more threads. Every operation moves a whole batch, so the items
+/// are counted separately.
+#[derive(Serialize, Default, Clone, Copy)]
+pub struct Counts {
+    pub enqueues: usize,
+    /// Dequeues which got at least one item
+    pub successful_dequeues: usize,
+    pub empty_dequeues: usize,
+    /// Dequeues which fell back to searching more of the queue, as reported by the
+    /// [stats](Handle::stats) of the handle
+    pub retried_dequeues: usize,
+    pub enqueued_items: usize,
+    pub dequeued_items: usize,
+}
+
+impl Counts {
+    pub fn dequeues(&self) -> usize {
+        self.successful_dequeues + self.empty_dequeues
+    }
+
+    /// Adds the counts of another thread.
+    pub fn add(&mut self, other: &Counts) {
+        self.enqueues += other.enqueues;
+        self.successful_dequeues += other.successful_dequeues;
+        self.empty_dequeues += other.empty_dequeues;
+        self.retried_dequeues += other.retried_dequeues;
+        self.enqueued_items += other.enqueued_items;
+        self.dequeued_items += other.dequeued_items;
+    }
+}
+
+impl WorkloadConfig {
+    /// The workloads of all threads to spawn.
+    pub fn threads(&self, producers: usize, consumers: usize) -> Vec<ThreadWorkload> {
+        let worker = match self.workload {
+            Workload::ProducerConsumer => {
+                let mut threads = vec![ThreadWorkload::Enqueue; producers];
+                threads.extend(vec![ThreadWorkload::Dequeue; consumers]);
+                return threads;
+            }
+            Workload::Pairs => ThreadWorkload::Pairs,
+            Workload::Mixed => ThreadWorkload::Mixed {
+                enqueue_percentage: self.enqueue_percentage,
+            },
+            Workload::Bursty => ThreadWorkload::Bursty {
+                burst_length: self.burst_length,
+            },
+            Workload::DrainRefill => ThreadWorkload::DrainRefill {
+                refill: self.burst_length,
+            },
+        };
+        vec![worker; producers + consumers]
+    }
+}
+
+/// Runs operations on a handle and records their outcome, and optionally their latency.
+struct Operations<'h, H, P> {
+    handle: &'h mut H,
+    counts: Counts,
+    latencies: Option<Latencies>,
+    payload_size: usize,
+    batch_size: usize,
+    work: Option<Work>,
+    rng: ThreadRng,
+    _payload: PhantomData<P>,
+}
+
+impl<H: Handle<P>, P: Payload> Operations<'_, H, P> {
+    /// Enqueues a single item, or a batch of them.
+    fn enqueue(&mut self) {
+        if self.batch_size > 1 {
+            let items = (0..self.batch_size)
+                .map(|_| P::new(ITEM, self.payload_size))
+                .collect();
+            self.timed(|handle| handle.enqueue_batch(items), |l| &mut l.enqueue);
+        } else {
+            let item = P::new(ITEM, self.payload_size);
+            self.timed(|handle| handle.enqueue(item), |l| &mut l.enqueue);
+        }
+        self.counts.enqueues += 1;
+        self.counts.enqueued_items += self.batch_size;
+        self.work();
+    }
+
+    /// Dequeues a single item, or a batch of them. Returns whether the dequeue got any item.
+    fn dequeue(&mut self) -> bool {
+        let dequeued = if self.batch_size > 1 {
+            let batch_size = self.batch_size;
+            let items = self.timed(
+                |handle| handle.dequeue_batch(batch_size),
+                |l| &mut l.dequeue,
+            );
+            let dequeued = items.len();
+            black_box(items);
+            dequeued
+        } else {
+            let item = self.timed(|handle| handle.dequeue(), |l| &mut l.dequeue);
+            let dequeued = usize::from(item.is_some());
+            black_box(item);
+            dequeued
+        };
+        self.counts.dequeued_items += dequeued;
+        if dequeued > 0 {
+            self.counts.successful_dequeues += 1;
+        } else {
+            self.counts.empty_dequeues += 1;
+        }
+        self.work();
+        dequeued > 0
+    }
+
+    /// Runs the operation, recording its latency if enabled.
+    fn timed<O>(
+        &mut self,
+        operation: impl FnOnce(&mut H) -> O,
+        histogram: impl FnOnce(&mut Latencies) -> &mut Histogram,
+    ) -> O {
+        match &mut self.latencies {
+            Some(latencies) => {
+                let start = Instant::now();
+                let output = operation(self.handle);
+                histogram(latencies).record(start.elapsed().as_nanos() as u64);
+                output
+            }
+            None => operation(self.handle),
+        }
+    }
+
+    fn work(&mut self) {
+        if let Some(work) = &self.work {
+            work.run(&mut self.rng);
+        }
+    }
+}
+
+/// What a thread did during the benchmark.
+#[derive(Serialize)]
+pub struct ThreadResult {
+    #[serde(flatten)]
+    pub workload: ThreadWorkload,
+    #[serde(flatten)]
+    pub counts: Counts,
+    #[serde(skip)]
+    pub latencies: Option<Latencies>,
+    /// Perf event counts during the measured window, if enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub perf: Option<BTreeMap<&'static str, u64>>,
+    /// The CPU the thread was pinned to, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<usize>,
+}
+
+impl ThreadWorkload {
+    /// Runs operations on the handle until `done` is set.
+    pub fn run<P: Payload>(
+        self,
+        handle: &mut impl Handle<P>,
+        done: &AtomicBool,
+        config: &BenchConfig,
+    ) -> ThreadResult {
+        let mut ops = Operations {
+            handle,
+            counts: Counts::default(),
+            latencies: config.latency.then(Latencies::default),
+            payload_size: config.payload.payload_size,
+            batch_size: config.workload.batch_size as usize,
+            work: Work::new(&config.work),
+            rng: rand::thread_rng(),
+            _payload: PhantomData,
+        };
+        let running = || !done.load(Ordering::Relaxed);
+        match self {
+            ThreadWorkload::Enqueue => {
+                while running() {
+                    ops.enqueue();
+                }
+            }
+            ThreadWorkload::Dequeue => {
+                while running() {
+                    ops.dequeue();
+                }
+            }
+            ThreadWorkload::Pairs => {
+                while running() {
+                    ops.enqueue();
+                    ops.dequeue();
+                }
+            }
+            ThreadWorkload::Mixed { enqueue_percentage } => {
+                while running() {
+                    if ops.rng.gen_ratio(enqueue_percentage, 100) {
+                        ops.enqueue();
+                    } else {
+                        ops.dequeue();
+                    }
+                }
+            }
+            ThreadWorkload::Bursty { burst_length } => {
+                while running() {
+                    // Bursts stop with the run, so long ones do not overshoot the measured window
+                    for _ in (0..burst_length).take_while(|_| running()) {
+                        ops.enqueue();
+                    }
+                    for _ in (0..burst_length).take_while(|_| running()) {
+                        ops.dequeue();
+                    }
+                }
+            }
+            ThreadWorkload::DrainRefill { refill } => {
+                while running() {
+                    while running() && ops.dequeue() {}
+                    for _ in (0..refill).take_while(|_| running()) {
+                        ops.enqueue();
+                    }
+                }
+            }
+        }
+        ops.counts.retried_dequeues = ops.handle.stats().retried_dequeues;
+        ThreadResult {
+            workload: self,
+            counts: ops.counts,
+            latencies: ops.latencies,
+            perf: None,
+            cpu: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    use clap::Parser;
+    use relaxed_queues::{strict_queues::ms::MSQueue, ConcurrentQueue};
+
+    use crate::BenchConfig;
+
+    use super::ThreadWorkload;
+
+    #[test]
+    fn counts_batches_as_one_operation() {
+        let config = BenchConfig::parse_from(["bench", "--batch-size", "4", "queue", "ms"]);
+        let queue = MSQueue::new();
+        let done = AtomicBool::new(false);
+        let counts = thread::scope(|s| {
+            let worker =
+                s.spawn(|| ThreadWorkload::Pairs.run::<i32>(&mut queue.register(), &done, &config));
+            thread::sleep(Duration::from_millis(10));
+            done.store(true, Ordering::Relaxed);
+            worker.join().unwrap().counts
+        });
+        assert!(counts.enqueues > 0);
+        assert_eq!(counts.enqueued_items, 4 * counts.enqueues);
+        assert_eq!(counts.dequeued_items, 4 * counts.successful_dequeues);
+        assert_eq!(counts.empty_dequeues, 0);
+    }
+}
@@ -0,0 +1,220 @@
+//! Runs parallel graph algorithms with the queues as work lists.
+
+#[cfg(not(target_env = "msvc"))]
+use jemallocator::Jemalloc;
+
+#[cfg(not(target_env = "msvc"))]
+#[global_allocator]
+static GLOBAL: Jemalloc = Jemalloc;
+
+use std::path::PathBuf;
+
+use clap::{builder::RangedU64ValueParser, Parser, Subcommand, ValueEnum};
+
+use relaxed_queues::{
+    apps::{
+        graph::Graph,
+        sssp::{self, Stats},
+    },
+    registry::{QueueSpec, QueueVisitor},
+    strict_queues::ms::MSQueue,
+    ConcurrentQueue,
+};
+
+fn main() {
+    let config = AppConfig::parse();
+
+    let graph = match &config.graph {
+        GraphSource::Random { nodes, edges } => {
+            Graph::random(*nodes, *edges, config.max_weight, config.seed)
+        }
+        GraphSource::Grid { width, height } => {
+            Graph::grid(*width, *height, config.max_weight, config.seed)
+        }
+        GraphSource::PowerLaw { nodes, degree } => {
+            Graph::power_law(*nodes, *degree, config.max_weight, config.seed)
+        }
+        GraphSource::File { path } => Graph::load(path).unwrap_or_else(|error| {
+            eprintln!("error: could not load {}: {error}", path.display());
+            std::process::exit(2);
+        }),
+    };
+    if config.source as usize >= graph.nodes() {
+        eprintln!("error: the graph has no node {}", config.source);
+        std::process::exit(2);
+    }
+    println!("nodes: {}", graph.nodes());
+    println!("edges: {}", graph.edges());
+
+    let stats = config.queue.visit(App {
+        graph: &graph,
+        config: &config,
+    });
+
+    println!("wall time (s): {}", stats.wall_time.as_secs_f64());
+    println!("reached nodes: {}", stats.reached());
+    println!("processed nodes: {}", stats.processed);
+    println!("stale nodes: {}", stats.stale);
+    println!("relaxations: {}", stats.relaxations);
+    println!("re-relaxations: {}", stats.re_relaxations());
+
+    if config.verify {
+        let expected = match config.algorithm {
+            Algorithm::Sssp => sssp::dijkstra(&graph, config.source),
+            // A single thread with a FIFO queue visits the nodes in BFS order
+            Algorithm::Bfs => sssp::bfs(&graph, config.source, &MSQueue::new(), 1).distances,
+        };
+        if stats.distances != expected {
+            eprintln!("error: the distances differ from the sequential ones");
+            std::process::exit(1);
+        }
+        println!("verified: true");
+    }
+}
+
+/// Runs the algorithm with whichever queue the registry builds as the work list.
+struct App<'a> {
+    graph: &'a Graph,
+    config: &'a AppConfig,
+}
+
+impl QueueVisitor<(u32, u64)> for App<'_> {
+    type Output = Stats;
+
+    fn visit<Q: ConcurrentQueue<(u32, u64)> + Sync>(self, queue: Q) -> Stats {
+        let (graph, config) = (self.graph, self.config);
+        match config.algorithm {
+            Algorithm::Bfs => sssp::bfs(graph, config.source, &queue, config.threads),
+            Algorithm::Sssp => sssp::sssp(graph, config.source, &queue, config.threads),
+        }
+    }
+}
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct AppConfig {
+    /// the algorithm to run.
+    #[arg(short, long, value_enum, default_value_t = Algorithm::Sssp)]
+    algorithm: Algorithm,
+
+    /// the queue used as the work list, as any spec the registry can build,
+    /// such as ms, rr(crossbeam,8), dra(ms,16,2) or rr(dra(ms,8,2),2).
+    #[arg(short, long, default_value = "ms")]
+    queue: QueueSpec,
+
+    /// number of worker threads.
+    #[arg(short, long, default_value_t = 4)]
+    threads: usize,
+
+    /// the node to start from.
+    #[arg(long, default_value_t = 0)]
+    source: u32,
+
+    /// largest edge weight of generated graphs, where weights are uniformly
+    /// random from 1 up to this.
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..))]
+    max_weight: u32,
+
+    /// seed for generated graphs.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// check the distances against a sequential run.
+    #[arg(long)]
+    verify: bool,
+
+    #[command(subcommand)]
+    graph: GraphSource,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+enum Algorithm {
+    Bfs,
+    Sssp,
+}
+
+#[derive(Subcommand)]
+enum GraphSource {
+    /// Random edges between uniformly chosen nodes
+    Random {
+        #[arg(long, value_parser = at_least_one())]
+        nodes: usize,
+        /// Number of undirected edges
+        #[arg(long)]
+        edges: usize,
+    },
+    /// A grid where nodes are connected to their four neighbors
+    Grid {
+        #[arg(long, value_parser = at_least_one())]
+        width: usize,
+        #[arg(long, value_parser = at_least_one())]
+        height: usize,
+    },
+    /// A scale-free graph with preferential attachment
+    PowerLaw {
+        #[arg(long, value_parser = at_least_one())]
+        nodes: usize,
+        /// Number of edges added with every new node
+        #[arg(long, default_value_t = 4)]
+        degree: usize,
+    },
+    /// Directed `source target [weight]` edges, one per line
+    File { path: PathBuf },
+}
+
+/// Parses a size of a generated graph, which has to be positive.
+fn at_least_one() -> RangedU64ValueParser<usize> {
+    RangedU64ValueParser::new().range(1..)
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use super::AppConfig;
+
+    #[test]
+    fn builds_queues_from_specs() {
+        let config = AppConfig::try_parse_from([
+            "apps",
+            "--queue",
+            "rr(dra(ms,8,2),2)",
+            "grid",
+            "--width",
+            "2",
+            "--height",
+            "2",
+        ])
+        .unwrap();
+        assert_eq!(config.queue.to_string(), "rr(dra(ms,8,2),2)");
+        assert!(AppConfig::try_parse_from([
+            "apps", "--queue", "dra(ms)", "grid", "--width", "2", "--height", "2"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn rejects_empty_graphs_and_weights() {
+        assert!(
+            AppConfig::try_parse_from(["apps", "grid", "--width", "2", "--height", "2"]).is_ok()
+        );
+        assert!(AppConfig::try_parse_from([
+            "apps",
+            "--max-weight",
+            "0",
+            "random",
+            "--nodes",
+            "4",
+            "--edges",
+            "8"
+        ])
+        .is_err());
+        assert!(
+            AppConfig::try_parse_from(["apps", "random", "--nodes", "0", "--edges", "8"]).is_err()
+        );
+        assert!(AppConfig::try_parse_from(["apps", "power-law", "--nodes", "0"]).is_err());
+        assert!(
+            AppConfig::try_parse_from(["apps", "grid", "--width", "0", "--height", "2"]).is_err()
+        );
+    }
+}
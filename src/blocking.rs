@@ -0,0 +1,210 @@
+//! Blocking dequeues for any queue, for consumers which would otherwise spin on an empty queue.
+//!
+//! Dequeuers first spin briefly, and then park on an [`EventCount`]. Enqueuers only pay for a
+//! fence and a load of the waiter count unless a thread is parked, so the queue operations
+//! themselves stay lock-free.
+
+use std::{
+    hint,
+    time::{Duration, Instant},
+};
+
+use crate::{eventcount::EventCount, ConcurrentQueue, Handle, HandleStats};
+
+/// How many dequeues to try before parking.
+const SPINS: usize = 100;
+
+/// How long a parked thread sleeps before looking again even if nothing was enqueued, as the
+/// dequeues of [`Relaxed`](crate::Relaxed) queues can miss items.
+const RECHECK: Duration = Duration::from_millis(1);
+
+/// A queue whose handles can wait for items, see [`BlockingHandle`].
+pub struct Blocking<Q> {
+    queue: Q,
+    event: EventCount,
+}
+
+impl<Q> Blocking<Q> {
+    pub fn new(queue: Q) -> Self {
+        Self {
+            queue,
+            event: EventCount::new(),
+        }
+    }
+}
+
+impl<T, Q: ConcurrentQueue<T>> ConcurrentQueue<T> for Blocking<Q> {
+    type QueueType = Q::QueueType;
+    type Handle<'q>
+        = BlockingHandle<'q, T, Q>
+    where
+        Self: 'q;
+
+    fn register(&self) -> BlockingHandle<'_, T, Q> {
+        BlockingHandle {
+            handle: self.queue.register(),
+            event: &self.event,
+            id: self.event.new_id(),
+        }
+    }
+
+    fn register_with_stream(&self, stream: usize) -> BlockingHandle<'_, T, Q> {
+        BlockingHandle {
+            handle: self.queue.register_with_stream(stream),
+            event: &self.event,
+            id: self.event.new_id(),
+        }
+    }
+}
+
+/// A handle of a [`Blocking`] queue. Enqueues wake the threads waiting in
+/// [`dequeue_blocking`](Self::dequeue_blocking) or [`dequeue_timeout`](Self::dequeue_timeout).
+pub struct BlockingHandle<'q, T, Q: ConcurrentQueue<T> + 'q> {
+    handle: Q::Handle<'q>,
+    event: &'q EventCount,
+    id: usize,
+}
+
+impl<T, Q: ConcurrentQueue<T>> BlockingHandle<'_, T, Q> {
+    /// Dequeues an item, waiting for one to be enqueued if the queue is empty.
+    pub fn dequeue_blocking(&mut self) -> T {
+        self.dequeue_until(None)
+            .expect("only times out with a deadline")
+    }
+
+    /// Dequeues an item, waiting at most `timeout` for one to be enqueued if the queue is empty.
+    pub fn dequeue_timeout(&mut self, timeout: Duration) -> Option<T> {
+        self.dequeue_until(Some(Instant::now() + timeout))
+    }
+
+    fn dequeue_until(&mut self, deadline: Option<Instant>) -> Option<T> {
+        for _ in 0..SPINS {
+            if let Some(item) = self.handle.dequeue() {
+                return Some(item);
+            }
+            hint::spin_loop();
+        }
+        loop {
+            let key = self.event.prepare_wait();
+            if let Some(item) = self.handle.dequeue() {
+                self.event.cancel_wait(self.id);
+                return Some(item);
+            }
+            let recheck = Instant::now() + RECHECK;
+            match deadline {
+                Some(deadline) if deadline <= Instant::now() => {
+                    self.event.cancel_wait(self.id);
+                    return None;
+                }
+                Some(deadline) => self.event.wait(self.id, key, deadline.min(recheck)),
+                None => self.event.wait(self.id, key, recheck),
+            }
+        }
+    }
+}
+
+impl<T, Q: ConcurrentQueue<T>> Handle<T> for BlockingHandle<'_, T, Q> {
+    fn enqueue(&mut self, item: T) {
+        self.handle.enqueue(item);
+        self.event.notify();
+    }
+
+    fn dequeue(&mut self) -> Option<T> {
+        self.handle.dequeue()
+    }
+
+    fn enqueue_batch(&mut self, items: Vec<T>) {
+        self.handle.enqueue_batch(items);
+        self.event.notify();
+    }
+
+    fn dequeue_batch(&mut self, max: usize) -> Vec<T> {
+        self.handle.dequeue_batch(max)
+    }
+
+    fn stats(&self) -> HandleStats {
+        self.handle.stats()
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use std::{
+        sync::Mutex,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        conformance::queue_conformance_tests,
+        relaxed_queues::dra_queue::DRaQueue,
+        strict_queues::{countable_wrapper::CountableWrapper, ms::MSQueue},
+        ConcurrentQueue, Handle,
+    };
+
+    use super::Blocking;
+
+    mod ms {
+        use super::*;
+
+        queue_conformance_tests!(strict, Blocking::new(MSQueue::new()));
+    }
+
+    mod dra {
+        use super::*;
+
+        queue_conformance_tests!(
+            relaxed,
+            Blocking::new(DRaQueue::<CountableWrapper<MSQueue<_>>, _>::new(4, 2))
+        );
+    }
+
+    #[test]
+    fn waits_for_enqueues() {
+        let queue = Blocking::new(MSQueue::new());
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                queue.register().enqueue(1);
+            });
+            assert_eq!(queue.register().dequeue_blocking(), 1);
+        });
+    }
+
+    #[test]
+    fn times_out_when_empty() {
+        let queue = Blocking::new(MSQueue::<usize>::new());
+        let start = Instant::now();
+        assert_eq!(
+            queue.register().dequeue_timeout(Duration::from_millis(20)),
+            None
+        );
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(queue.event.waiters(), 0);
+    }
+
+    #[test]
+    fn wakes_every_parked_consumer() {
+        const CONSUMERS: usize = 4;
+        const ITEMS: usize = 100;
+        let queue = Blocking::new(DRaQueue::<CountableWrapper<MSQueue<_>>, _>::new(4, 2));
+        let collected = Mutex::new(Vec::new());
+        thread::scope(|s| {
+            for _ in 0..CONSUMERS {
+                s.spawn(|| {
+                    let mut handle = queue.register();
+                    let items: Vec<_> = (0..ITEMS).map(|_| handle.dequeue_blocking()).collect();
+                    collected.lock().unwrap().extend(items);
+                });
+            }
+            thread::sleep(Duration::from_millis(10));
+            let mut handle = queue.register();
+            for i in 0..CONSUMERS * ITEMS {
+                handle.enqueue(i);
+            }
+        });
+        let mut collected = collected.into_inner().unwrap();
+        collected.sort_unstable();
+        assert_eq!(collected, (0..CONSUMERS * ITEMS).collect::<Vec<_>>());
+    }
+}
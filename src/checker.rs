@@ -0,0 +1,654 @@
+//! Offline checking of recorded queue histories.
+//!
+//! Handles are wrapped in a [`RecordingHandle`] through a shared [`Recorder`], which stamps every
+//! invocation and response with a global logical clock. The collected [`History`] can then be
+//! checked for strict FIFO linearizability or for bounded out-of-order (k-FIFO) behaviour.
+//!
+//! All checks assume that every enqueued value is distinct, which is what lets them run in
+//! polynomial time. They follow the violation patterns for queues from Henzinger et al.,
+//! "Aspect-Oriented Linearizability Proofs" (CONCUR 2013).
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    hash::Hash,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use rand::Rng;
+
+use crate::{drain, ConcurrentQueue, Handle, HandleStats, QueueType, Relaxed, Strict};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OperationKind<T> {
+    Enqueue(T),
+    Dequeue(Option<T>),
+}
+
+/// A single completed operation, with logical invocation and response times.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Operation<T> {
+    pub thread: usize,
+    pub kind: OperationKind<T>,
+    pub invoked: usize,
+    pub responded: usize,
+}
+
+impl<T> Operation<T> {
+    /// Is this operation over before `other` starts?
+    pub fn precedes(&self, other: &Operation<T>) -> bool {
+        self.responded < other.invoked
+    }
+}
+
+/// Hands out recording handles and collects their operations once they are dropped.
+pub struct Recorder<T> {
+    clock: AtomicUsize,
+    next_thread: AtomicUsize,
+    operations: Mutex<Vec<Operation<T>>>,
+}
+
+impl<T> Default for Recorder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Recorder<T> {
+    pub fn new() -> Self {
+        Self {
+            clock: AtomicUsize::new(0),
+            next_thread: AtomicUsize::new(0),
+            operations: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Wraps a queue handle so that all its operations end up in this recorder.
+    pub fn record<H: Handle<T>>(&self, handle: H) -> RecordingHandle<'_, H, T> {
+        RecordingHandle {
+            handle,
+            recorder: self,
+            thread: self.next_thread.fetch_add(1, Ordering::Relaxed),
+            operations: Vec::new(),
+        }
+    }
+
+    /// Returns the history of all operations from the recording handles dropped so far.
+    pub fn into_history(self) -> History<T> {
+        History::from_operations(self.operations.into_inner().unwrap())
+    }
+
+    fn tick(&self) -> usize {
+        self.clock.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+pub struct RecordingHandle<'r, H, T> {
+    handle: H,
+    recorder: &'r Recorder<T>,
+    thread: usize,
+    operations: Vec<Operation<T>>,
+}
+
+impl<H: Handle<T>, T: Clone> Handle<T> for RecordingHandle<'_, H, T> {
+    fn enqueue(&mut self, item: T) {
+        let invoked = self.recorder.tick();
+        self.handle.enqueue(item.clone());
+        let responded = self.recorder.tick();
+        self.operations.push(Operation {
+            thread: self.thread,
+            kind: OperationKind::Enqueue(item),
+            invoked,
+            responded,
+        });
+    }
+
+    fn dequeue(&mut self) -> Option<T> {
+        let invoked = self.recorder.tick();
+        let item = self.handle.dequeue();
+        let responded = self.recorder.tick();
+        self.operations.push(Operation {
+            thread: self.thread,
+            kind: OperationKind::Dequeue(item.clone()),
+            invoked,
+            responded,
+        });
+        item
+    }
+
+    fn stats(&self) -> HandleStats {
+        self.handle.stats()
+    }
+}
+
+impl<H, T> Drop for RecordingHandle<'_, H, T> {
+    fn drop(&mut self) {
+        // Don't poison the whole recording if a recording thread panics
+        if let Ok(mut operations) = self.recorder.operations.lock() {
+            operations.append(&mut self.operations);
+        }
+    }
+}
+
+/// A way in which a history deviates from the queue specification it was checked against.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation<T> {
+    /// The same value was enqueued twice, so the history cannot be checked.
+    DuplicateEnqueue { value: T },
+    /// A value was dequeued without being enqueued before.
+    Fresh { dequeue: Operation<T> },
+    /// A value was dequeued more than once.
+    Repeated { value: T },
+    /// `first` was enqueued before `second`, but `second` was dequeued before `first`, or `first`
+    /// was never dequeued at all.
+    Order { first: T, second: T },
+    /// A value was never dequeued, although the queue was drained at the end.
+    Lost { value: T },
+    /// A dequeue returned empty, although the queue was non-empty for its whole duration.
+    Empty { dequeue: Operation<T> },
+    /// `value` overtook `rank_error` older items when dequeued, which is more than allowed.
+    RankError {
+        value: T,
+        rank_error: usize,
+        bound: usize,
+    },
+}
+
+/// What a value went through in a history.
+struct Lifetime<'h, T> {
+    enqueue: Option<&'h Operation<T>>,
+    dequeue: Option<&'h Operation<T>>,
+}
+
+/// A complete history of queue operations, recorded from any number of threads.
+pub struct History<T> {
+    operations: Vec<Operation<T>>,
+}
+
+impl<T> History<T> {
+    pub fn from_operations(mut operations: Vec<Operation<T>>) -> Self {
+        operations.sort_by_key(|op| op.invoked);
+        Self { operations }
+    }
+
+    /// All operations, ordered by invocation time.
+    pub fn operations(&self) -> &[Operation<T>] {
+        &self.operations
+    }
+
+    pub fn enqueues(&self) -> usize {
+        self.operations
+            .iter()
+            .filter(|op| matches!(op.kind, OperationKind::Enqueue(_)))
+            .count()
+    }
+
+    pub fn successful_dequeues(&self) -> usize {
+        self.operations
+            .iter()
+            .filter(|op| matches!(op.kind, OperationKind::Dequeue(Some(_))))
+            .count()
+    }
+}
+
+impl<T: Clone + Eq + Hash> History<T> {
+    /// Checks that the history is linearizable with respect to a strict FIFO queue.
+    pub fn check_linearizable(&self) -> Result<(), Violation<T>> {
+        let lifetimes = self.lifetimes()?;
+        if let Some((first, second)) = self.overtakes(&lifetimes).next() {
+            return Err(Violation::Order {
+                first: first.clone(),
+                second: second.clone(),
+            });
+        }
+        self.check_empty_dequeues(&lifetimes)
+    }
+
+    /// Checks that no dequeued value overtook more than `k` values that were definitely
+    /// enqueued before it. Returns the largest such rank error found.
+    ///
+    /// Empty dequeues are not checked, as relaxed queues are allowed to miss items.
+    pub fn check_k_fifo(&self, k: usize) -> Result<usize, Violation<T>> {
+        let lifetimes = self.lifetimes()?;
+        let mut rank_errors: HashMap<&T, usize> = HashMap::new();
+        for (_, second) in self.overtakes(&lifetimes) {
+            *rank_errors.entry(second).or_default() += 1;
+        }
+        match rank_errors.into_iter().max_by_key(|&(_, error)| error) {
+            Some((value, rank_error)) if rank_error > k => Err(Violation::RankError {
+                value: value.clone(),
+                rank_error,
+                bound: k,
+            }),
+            Some((_, rank_error)) => Ok(rank_error),
+            None => Ok(0),
+        }
+    }
+
+    /// The largest number of older values any dequeued value overtook.
+    pub fn max_rank_error(&self) -> Result<usize, Violation<T>> {
+        self.check_k_fifo(usize::MAX)
+    }
+
+    /// Pairs each value with its enqueue and dequeue, catching fresh and repeated values.
+    fn lifetimes(&self) -> Result<HashMap<&T, Lifetime<'_, T>>, Violation<T>> {
+        let mut lifetimes: HashMap<&T, Lifetime<T>> = HashMap::new();
+        for op in &self.operations {
+            match &op.kind {
+                OperationKind::Enqueue(value) => match lifetimes.entry(value) {
+                    Entry::Occupied(mut entry) if entry.get().enqueue.is_none() => {
+                        entry.get_mut().enqueue = Some(op);
+                    }
+                    Entry::Occupied(_) => {
+                        return Err(Violation::DuplicateEnqueue {
+                            value: value.clone(),
+                        })
+                    }
+                    Entry::Vacant(entry) => {
+                        entry.insert(Lifetime {
+                            enqueue: Some(op),
+                            dequeue: None,
+                        });
+                    }
+                },
+                OperationKind::Dequeue(Some(value)) => {
+                    let lifetime = lifetimes.entry(value).or_insert(Lifetime {
+                        enqueue: None,
+                        dequeue: None,
+                    });
+                    if lifetime.dequeue.is_some() {
+                        return Err(Violation::Repeated {
+                            value: value.clone(),
+                        });
+                    }
+                    lifetime.dequeue = Some(op);
+                }
+                OperationKind::Dequeue(None) => {}
+            }
+        }
+
+        for lifetime in lifetimes.values() {
+            match (lifetime.enqueue, lifetime.dequeue) {
+                (None, Some(dequeue)) => {
+                    return Err(Violation::Fresh {
+                        dequeue: dequeue.clone(),
+                    })
+                }
+                (Some(enqueue), Some(dequeue)) if dequeue.precedes(enqueue) => {
+                    return Err(Violation::Fresh {
+                        dequeue: dequeue.clone(),
+                    })
+                }
+                _ => {}
+            }
+        }
+
+        Ok(lifetimes)
+    }
+
+    /// All pairs `(first, second)` where `first` was definitely enqueued before `second`, but
+    /// `second` was definitely dequeued before `first`.
+    ///
+    /// This is quadratic in the number of dequeued values, which is fine for test-sized histories.
+    fn overtakes<'a>(
+        &'a self,
+        lifetimes: &'a HashMap<&'a T, Lifetime<'a, T>>,
+    ) -> impl Iterator<Item = (&'a T, &'a T)> + 'a {
+        lifetimes.iter().flat_map(move |(second, second_life)| {
+            lifetimes
+                .iter()
+                .filter(move |(_, first_life)| {
+                    let (Some(first_enq), Some(second_enq), Some(second_deq)) =
+                        (first_life.enqueue, second_life.enqueue, second_life.dequeue)
+                    else {
+                        return false;
+                    };
+                    first_enq.precedes(second_enq)
+                        && first_life
+                            .dequeue
+                            .is_none_or(|first_deq| second_deq.precedes(first_deq))
+                })
+                .map(move |(first, _)| (*first, *second))
+        })
+    }
+
+    /// Checks that every empty dequeue could have happened while the queue was empty, meaning
+    /// that its duration is not covered by values that were definitely in the queue.
+    fn check_empty_dequeues(
+        &self,
+        lifetimes: &HashMap<&T, Lifetime<T>>,
+    ) -> Result<(), Violation<T>> {
+        // Intervals where a value is definitely in the queue, sorted by start
+        let mut present: Vec<(usize, usize)> = lifetimes
+            .values()
+            .filter_map(|lifetime| {
+                let enqueue = lifetime.enqueue?;
+                let end = lifetime.dequeue.map_or(usize::MAX, |deq| deq.invoked);
+                (enqueue.responded < end).then_some((enqueue.responded, end))
+            })
+            .collect();
+        present.sort_unstable();
+
+        for dequeue in &self.operations {
+            if dequeue.kind != OperationKind::Dequeue(None) {
+                continue;
+            }
+            // The open interval (invoked, responded) is covered if we can walk through it using
+            // overlapping open intervals where the queue is non-empty.
+            let mut covered_until = dequeue.invoked;
+            for &(start, end) in &present {
+                if start > covered_until {
+                    break;
+                }
+                if start < covered_until || start == dequeue.invoked {
+                    covered_until = covered_until.max(end);
+                }
+                if covered_until >= dequeue.responded {
+                    return Err(Violation::Empty {
+                        dequeue: dequeue.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The specification a queue type is checked against.
+pub trait Specification: QueueType {
+    /// Checks a complete history, where all values have been drained from the queue.
+    fn check<T: Clone + Eq + Hash>(history: &History<T>, k: usize) -> Result<(), Violation<T>>;
+}
+
+impl Specification for Strict {
+    fn check<T: Clone + Eq + Hash>(history: &History<T>, _k: usize) -> Result<(), Violation<T>> {
+        history.check_linearizable()
+    }
+}
+
+impl Specification for Relaxed {
+    fn check<T: Clone + Eq + Hash>(history: &History<T>, k: usize) -> Result<(), Violation<T>> {
+        // No items can be lost, even if they can be dequeued out of order
+        if let Some(value) = lost_value(history) {
+            return Err(Violation::Lost { value });
+        }
+        history.check_k_fifo(k).map(|_| ())
+    }
+}
+
+/// Finds a value which was enqueued but never dequeued.
+fn lost_value<T: Clone + Eq + Hash>(history: &History<T>) -> Option<T> {
+    let mut lost: HashMap<&T, bool> = HashMap::new();
+    for op in history.operations() {
+        match &op.kind {
+            OperationKind::Enqueue(value) => {
+                lost.entry(value).or_insert(true);
+            }
+            OperationKind::Dequeue(Some(value)) => {
+                lost.insert(value, false);
+            }
+            OperationKind::Dequeue(None) => {}
+        }
+    }
+    lost.into_iter()
+        .find(|&(_, lost)| lost)
+        .map(|(value, _)| value.clone())
+}
+
+/// Runs a random mix of enqueues and dequeues on `threads` threads, then drains the queue from
+/// the main thread, and returns the recorded history. All enqueued values are distinct.
+pub fn record_random_history<Q>(queue: &Q, threads: usize, operations: usize) -> History<usize>
+where
+    Q: ConcurrentQueue<usize> + Sync,
+{
+    let recorder = Recorder::new();
+    thread::scope(|s| {
+        for t in 0..threads {
+            let recorder = &recorder;
+            s.spawn(move || {
+                let mut handle = recorder.record(queue.register());
+                let mut rng = rand::thread_rng();
+                for i in 0..operations {
+                    if rng.gen_bool(0.5) {
+                        handle.enqueue(t * operations + i);
+                    } else {
+                        handle.dequeue();
+                    }
+                }
+            });
+        }
+    });
+
+    let mut handle = recorder.record(queue.register());
+    drain(|| handle.dequeue());
+    drop(handle);
+
+    recorder.into_history()
+}
+
+/// Records a random concurrent history on `queue` and checks it against the specification of its
+/// queue type, allowing relaxed queues a rank error of at most `k`.
+pub fn check_random_history<Q>(
+    queue: &Q,
+    threads: usize,
+    operations: usize,
+    k: usize,
+) -> Result<(), Violation<usize>>
+where
+    Q: ConcurrentQueue<usize> + Sync,
+    Q::QueueType: Specification,
+{
+    let history = record_random_history(queue, threads, operations);
+    Q::QueueType::check(&history, k)
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use crate::{
+        relaxed_queues::{
+            dcbo_queue::DCBOQueue, dra_queue::DRaQueue, round_robin_queue::RoundRobinQueue,
+        },
+        strict_queues::{countable_wrapper::CountableWrapper, ms::MSQueue},
+        ConcurrentQueue, Handle,
+    };
+
+    use super::{check_random_history, History, Operation, OperationKind, Recorder, Violation};
+
+    fn op(kind: OperationKind<usize>, invoked: usize, responded: usize) -> Operation<usize> {
+        Operation {
+            thread: 0,
+            kind,
+            invoked,
+            responded,
+        }
+    }
+
+    use OperationKind::{Dequeue, Enqueue};
+
+    const RUNS: usize = if cfg!(miri) { 1 } else { 10 };
+    const THREADS: usize = if cfg!(miri) { 2 } else { 4 };
+    const OPERATIONS: usize = if cfg!(miri) { 20 } else { 200 };
+
+    #[test]
+    fn sequential_fifo_is_linearizable() {
+        let history = History::from_operations(vec![
+            op(Enqueue(1), 0, 1),
+            op(Enqueue(2), 2, 3),
+            op(Dequeue(Some(1)), 4, 5),
+            op(Dequeue(Some(2)), 6, 7),
+            op(Dequeue(None), 8, 9),
+        ]);
+        assert_eq!(history.check_linearizable(), Ok(()));
+        assert_eq!(history.max_rank_error(), Ok(0));
+    }
+
+    #[test]
+    fn overlapping_operations_can_reorder() {
+        let history = History::from_operations(vec![
+            op(Enqueue(1), 0, 3),
+            op(Enqueue(2), 1, 2),
+            op(Dequeue(Some(2)), 4, 5),
+            op(Dequeue(Some(1)), 6, 7),
+        ]);
+        assert_eq!(history.check_linearizable(), Ok(()));
+    }
+
+    #[test]
+    fn detects_out_of_order() {
+        let history = History::from_operations(vec![
+            op(Enqueue(1), 0, 1),
+            op(Enqueue(2), 2, 3),
+            op(Dequeue(Some(2)), 4, 5),
+            op(Dequeue(Some(1)), 6, 7),
+        ]);
+        assert_eq!(
+            history.check_linearizable(),
+            Err(Violation::Order {
+                first: 1,
+                second: 2
+            })
+        );
+        assert_eq!(history.check_k_fifo(1), Ok(1));
+        assert!(matches!(
+            history.check_k_fifo(0),
+            Err(Violation::RankError { value: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn detects_fresh_and_repeated() {
+        let fresh =
+            History::from_operations(vec![op(Dequeue(Some(1)), 0, 1), op(Enqueue(1), 2, 3)]);
+        assert!(matches!(
+            fresh.check_linearizable(),
+            Err(Violation::Fresh { .. })
+        ));
+
+        let repeated = History::from_operations(vec![
+            op(Enqueue(1), 0, 1),
+            op(Dequeue(Some(1)), 2, 3),
+            op(Dequeue(Some(1)), 4, 5),
+        ]);
+        assert_eq!(
+            repeated.check_linearizable(),
+            Err(Violation::Repeated { value: 1 })
+        );
+    }
+
+    #[test]
+    fn detects_bad_empty() {
+        let history = History::from_operations(vec![
+            op(Enqueue(1), 0, 1),
+            op(Dequeue(None), 2, 3),
+            op(Dequeue(Some(1)), 4, 5),
+        ]);
+        assert!(matches!(
+            history.check_linearizable(),
+            Err(Violation::Empty { .. })
+        ));
+
+        // Two values together keep the queue non-empty during the whole dequeue
+        let covered = History::from_operations(vec![
+            op(Enqueue(1), 0, 1),
+            op(Enqueue(2), 2, 3),
+            op(Dequeue(None), 4, 9),
+            op(Dequeue(Some(1)), 5, 7),
+            op(Dequeue(Some(2)), 10, 11),
+        ]);
+        assert!(matches!(
+            covered.check_linearizable(),
+            Err(Violation::Empty { .. })
+        ));
+
+        // But the queue could have been empty if the dequeues overlap
+        let gap = History::from_operations(vec![
+            op(Enqueue(1), 0, 1),
+            op(Enqueue(2), 6, 7),
+            op(Dequeue(None), 2, 9),
+            op(Dequeue(Some(1)), 3, 5),
+            op(Dequeue(Some(2)), 8, 10),
+        ]);
+        assert_eq!(gap.check_linearizable(), Ok(()));
+    }
+
+    #[test]
+    fn recorded_sequential_history() {
+        let queue = MSQueue::new();
+        let recorder = Recorder::new();
+        let mut handle = recorder.record(queue.register());
+        handle.enqueue(1);
+        handle.enqueue(2);
+        assert_eq!(handle.dequeue(), Some(1));
+        assert_eq!(handle.dequeue(), Some(2));
+        assert_eq!(handle.dequeue(), None);
+        drop(handle);
+
+        let history = recorder.into_history();
+        assert_eq!(history.operations().len(), 5);
+        assert_eq!(history.check_linearizable(), Ok(()));
+    }
+
+    #[test]
+    fn round_robin_sequential_rank_error() {
+        let queue = RoundRobinQueue::<MSQueue<_>, _>::new(4);
+        let recorder = Recorder::new();
+        let mut handle = recorder.record(queue.register());
+        for i in 0..50 {
+            handle.enqueue(i);
+        }
+        while handle.dequeue().is_some() {}
+        drop(handle);
+
+        // A single handle can at most skip the items in the other sub-queues of one round
+        let history = recorder.into_history();
+        assert!(history.check_k_fifo(3).is_ok());
+        assert_eq!(history.successful_dequeues(), 50);
+    }
+
+    #[test]
+    fn ms_queue_random_histories() {
+        for _ in 0..RUNS {
+            let queue = MSQueue::new();
+            assert_eq!(check_random_history(&queue, THREADS, OPERATIONS, 0), Ok(()));
+        }
+    }
+
+    // The relaxed queues give no hard bound on the rank error, so we only check that the
+    // histories are otherwise sound.
+
+    #[test]
+    fn dra_queue_random_histories() {
+        for _ in 0..RUNS {
+            let queue = DRaQueue::<CountableWrapper<MSQueue<_>>, _>::new(4, 2);
+            assert_eq!(
+                check_random_history(&queue, THREADS, OPERATIONS, usize::MAX),
+                Ok(())
+            );
+        }
+    }
+
+    #[test]
+    fn round_robin_queue_random_histories() {
+        for _ in 0..RUNS {
+            let queue = RoundRobinQueue::<MSQueue<_>, _>::new(4);
+            assert_eq!(
+                check_random_history(&queue, THREADS, OPERATIONS, usize::MAX),
+                Ok(())
+            );
+        }
+    }
+
+    #[test]
+    fn dcbo_queue_random_histories() {
+        for _ in 0..RUNS {
+            let queue = DCBOQueue::<CountableWrapper<MSQueue<_>>, _>::new(4, 2);
+            assert_eq!(
+                check_random_history(&queue, THREADS, OPERATIONS, usize::MAX),
+                Ok(())
+            );
+        }
+    }
+}
@@ -0,0 +1,437 @@
+//! Generic tests that every queue in the crate should pass.
+//!
+//! Use [`queue_conformance_tests`] for [`ConcurrentQueue`] implementations and
+//! [`sub_queue_conformance_tests`] for [`ConcurrentSubQueue`] implementations, inside a test
+//! module of the file implementing the queue.
+//!
+//! The tests also run under Miri, where they use fewer threads and items:
+//! ```sh
+//! cargo +nightly miri test --lib
+//! ```
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use crate::{drain, strict_queues::ConcurrentSubQueue, ConcurrentQueue, Handle};
+
+const PRODUCERS: usize = if cfg!(miri) { 2 } else { 4 };
+const CONSUMERS: usize = if cfg!(miri) { 2 } else { 4 };
+const ITEMS_PER_PRODUCER: usize = if cfg!(miri) { 20 } else { 1000 };
+const SINGLE_THREAD_ITEMS: usize = if cfg!(miri) { 20 } else { 100 };
+const BATCH_SIZE: usize = 8;
+
+/// Hands out payloads and keeps track of how many times each of them has been dropped.
+pub struct DropTracker {
+    drops: Arc<Vec<AtomicUsize>>,
+}
+
+impl DropTracker {
+    pub fn new(items: usize) -> Self {
+        Self {
+            drops: Arc::new((0..items).map(|_| AtomicUsize::new(0)).collect()),
+        }
+    }
+
+    pub fn item(&self, id: usize) -> Tracked {
+        Tracked {
+            id,
+            drops: self.drops.clone(),
+        }
+    }
+
+    pub fn drops(&self, id: usize) -> usize {
+        self.drops[id].load(Ordering::Relaxed)
+    }
+
+    /// Checks that every handed out item has been dropped exactly once.
+    pub fn assert_all_dropped_once(&self) {
+        for id in 0..self.drops.len() {
+            assert_eq!(
+                self.drops(id),
+                1,
+                "item {id} dropped the wrong number of times"
+            );
+        }
+    }
+}
+
+/// A payload which records its drop in the [`DropTracker`] it came from.
+pub struct Tracked {
+    id: usize,
+    drops: Arc<Vec<AtomicUsize>>,
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.drops[self.id].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Checks that every value in `0..count` was collected exactly once.
+fn assert_all_exactly_once(mut collected: Vec<usize>, count: usize) {
+    assert_eq!(collected.len(), count, "lost or duplicated items");
+    collected.sort_unstable();
+    for (i, &value) in collected.iter().enumerate() {
+        assert_eq!(value, i, "lost or duplicated items");
+    }
+}
+
+/// Runs producers and consumers concurrently, with `enqueue` and `dequeue` run on thread-local
+/// handles created by `handle` from the index of the thread. Returns everything the consumers got.
+fn produce_consume<H, T>(
+    handle: impl Fn(usize) -> H + Sync,
+    enqueue: impl Fn(&mut H, T) + Sync,
+    dequeue: impl Fn(&mut H) -> Option<T> + Sync,
+    item: impl Fn(usize, usize) -> T + Sync,
+) -> Vec<Vec<T>>
+where
+    T: Send,
+{
+    let dequeued = AtomicUsize::new(0);
+    let collected = Mutex::new(Vec::new());
+    thread::scope(|s| {
+        for p in 0..PRODUCERS {
+            let (handle, enqueue, item) = (&handle, &enqueue, &item);
+            s.spawn(move || {
+                let mut h = handle(p);
+                for i in 0..ITEMS_PER_PRODUCER {
+                    enqueue(&mut h, item(p, i));
+                }
+            });
+        }
+        for c in 0..CONSUMERS {
+            let (handle, dequeue, dequeued, collected) = (&handle, &dequeue, &dequeued, &collected);
+            s.spawn(move || {
+                let mut h = handle(PRODUCERS + c);
+                let mut local = Vec::new();
+                while dequeued.load(Ordering::Relaxed) < PRODUCERS * ITEMS_PER_PRODUCER {
+                    if let Some(item) = dequeue(&mut h) {
+                        local.push(item);
+                        dequeued.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                collected.lock().unwrap().push(local);
+            });
+        }
+    });
+    collected.into_inner().unwrap()
+}
+
+/// Checks that every consumer saw the items of each producer in the order they were enqueued.
+fn assert_per_producer_order(collected: Vec<Vec<(usize, usize)>>) {
+    for local in collected {
+        let mut last_seen = [None; PRODUCERS];
+        for (producer, seq) in local {
+            assert!(
+                last_seen[producer] < Some(seq),
+                "items from producer {producer} dequeued out of order"
+            );
+            last_seen[producer] = Some(seq);
+        }
+    }
+}
+
+pub fn single_thread_fifo<Q: ConcurrentQueue<usize>>(queue: Q) {
+    let mut handle = queue.register();
+    assert_eq!(handle.dequeue(), None);
+    for i in 0..5 {
+        handle.enqueue(i);
+    }
+    assert_eq!(handle.dequeue(), Some(0));
+    assert_eq!(handle.dequeue(), Some(1));
+    for i in 5..10 {
+        handle.enqueue(i);
+    }
+    for i in 2..10 {
+        assert_eq!(handle.dequeue(), Some(i));
+    }
+    assert_eq!(handle.dequeue(), None);
+    assert_eq!(handle.dequeue(), None);
+}
+
+pub fn single_thread_no_loss<Q: ConcurrentQueue<usize>>(queue: Q) {
+    let mut handle = queue.register();
+    for i in 0..SINGLE_THREAD_ITEMS {
+        handle.enqueue(i);
+    }
+    let mut collected = drain(|| handle.dequeue());
+    for i in SINGLE_THREAD_ITEMS..2 * SINGLE_THREAD_ITEMS {
+        handle.enqueue(i);
+    }
+    collected.extend(drain(|| handle.dequeue()));
+    assert_all_exactly_once(collected, 2 * SINGLE_THREAD_ITEMS);
+    assert_eq!(handle.dequeue(), None);
+}
+
+pub fn multi_thread_no_loss<Q: ConcurrentQueue<usize> + Sync>(queue: Q) {
+    let collected = produce_consume(
+        |_| queue.register(),
+        |h, item| h.enqueue(item),
+        |h| h.dequeue(),
+        |p, i| p * ITEMS_PER_PRODUCER + i,
+    );
+    assert_all_exactly_once(collected.concat(), PRODUCERS * ITEMS_PER_PRODUCER);
+    assert_eq!(queue.register().dequeue(), None);
+}
+
+pub fn single_thread_batch_fifo<Q: ConcurrentQueue<usize>>(queue: Q) {
+    let mut handle = queue.register();
+    assert_eq!(handle.dequeue_batch(BATCH_SIZE), []);
+    handle.enqueue_batch((0..5).collect());
+    handle.enqueue(5);
+    handle.enqueue_batch(Vec::new());
+    handle.enqueue_batch((6..10).collect());
+    assert_eq!(handle.dequeue_batch(3), [0, 1, 2]);
+    assert_eq!(handle.dequeue(), Some(3));
+    assert_eq!(handle.dequeue_batch(100), [4, 5, 6, 7, 8, 9]);
+    assert_eq!(handle.dequeue_batch(BATCH_SIZE), []);
+}
+
+pub fn multi_thread_batches_no_loss<Q: ConcurrentQueue<usize> + Sync>(queue: Q) {
+    let collected = produce_consume(
+        |_| (queue.register(), Vec::new()),
+        |(h, batch), item| {
+            batch.push(item);
+            if batch.len() == BATCH_SIZE || item % ITEMS_PER_PRODUCER == ITEMS_PER_PRODUCER - 1 {
+                h.enqueue_batch(std::mem::take(batch));
+            }
+        },
+        |(h, batch)| {
+            if batch.is_empty() {
+                *batch = h.dequeue_batch(BATCH_SIZE);
+                batch.reverse();
+            }
+            batch.pop()
+        },
+        |p, i| p * ITEMS_PER_PRODUCER + i,
+    );
+    assert_all_exactly_once(collected.concat(), PRODUCERS * ITEMS_PER_PRODUCER);
+    assert_eq!(queue.register().dequeue_batch(BATCH_SIZE), []);
+}
+
+pub fn per_producer_order<Q: ConcurrentQueue<(usize, usize)> + Sync>(queue: Q) {
+    let collected = produce_consume(
+        |_| queue.register(),
+        |h, item| h.enqueue(item),
+        |h| h.dequeue(),
+        |p, i| (p, i),
+    );
+    assert_per_producer_order(collected);
+}
+
+pub fn drops_payloads<Q: ConcurrentQueue<Tracked>>(queue: Q) {
+    let tracker = DropTracker::new(SINGLE_THREAD_ITEMS);
+    let mut handle = queue.register();
+    for id in 0..SINGLE_THREAD_ITEMS {
+        handle.enqueue(tracker.item(id));
+    }
+    let dequeued: Vec<_> = (0..SINGLE_THREAD_ITEMS / 2)
+        .filter_map(|_| handle.dequeue())
+        .collect();
+    for item in &dequeued {
+        assert_eq!(tracker.drops(item.id), 0, "dequeued item already dropped");
+    }
+    drop(dequeued);
+    drop(handle);
+    drop(queue);
+    tracker.assert_all_dropped_once();
+}
+
+pub fn sub_queue_single_thread_fifo<S: ConcurrentSubQueue<usize>>(queue: S) {
+    let mut lock = queue.new_lock(0);
+    assert_eq!(queue.dequeue(&mut lock), None);
+    for i in 0..5 {
+        queue.enqueue(i, &mut lock);
+    }
+    assert_eq!(queue.dequeue(&mut lock), Some(0));
+    assert_eq!(queue.dequeue(&mut lock), Some(1));
+    for i in 5..10 {
+        queue.enqueue(i, &mut lock);
+    }
+    for i in 2..10 {
+        assert_eq!(queue.dequeue(&mut lock), Some(i));
+    }
+    assert_eq!(queue.dequeue(&mut lock), None);
+}
+
+pub fn sub_queue_single_thread_batch_fifo<S: ConcurrentSubQueue<usize>>(queue: S) {
+    let mut lock = queue.new_lock(0);
+    assert_eq!(queue.dequeue_batch(BATCH_SIZE, &mut lock), []);
+    queue.enqueue_batch((0..5).collect(), &mut lock);
+    queue.enqueue(5, &mut lock);
+    queue.enqueue_batch((6..10).collect(), &mut lock);
+    assert_eq!(queue.dequeue_batch(3, &mut lock), [0, 1, 2]);
+    assert_eq!(queue.dequeue(&mut lock), Some(3));
+    assert_eq!(queue.dequeue_batch(100, &mut lock), [4, 5, 6, 7, 8, 9]);
+    assert_eq!(queue.dequeue_batch(BATCH_SIZE, &mut lock), []);
+}
+
+pub fn sub_queue_single_thread_no_loss<S: ConcurrentSubQueue<usize>>(queue: S) {
+    let mut lock = queue.new_lock(0);
+    for i in 0..SINGLE_THREAD_ITEMS {
+        queue.enqueue(i, &mut lock);
+    }
+    let mut collected = drain(|| queue.dequeue(&mut lock));
+    for i in SINGLE_THREAD_ITEMS..2 * SINGLE_THREAD_ITEMS {
+        queue.enqueue(i, &mut lock);
+    }
+    collected.extend(drain(|| queue.dequeue(&mut lock)));
+    assert_all_exactly_once(collected, 2 * SINGLE_THREAD_ITEMS);
+    assert_eq!(queue.dequeue(&mut lock), None);
+}
+
+pub fn sub_queue_multi_thread_no_loss<S: ConcurrentSubQueue<usize> + Sync>(queue: S) {
+    let collected = produce_consume(
+        |stream| queue.new_lock(stream),
+        |lock, item| queue.enqueue(item, lock),
+        |lock| queue.dequeue(lock),
+        |p, i| p * ITEMS_PER_PRODUCER + i,
+    );
+    assert_all_exactly_once(collected.concat(), PRODUCERS * ITEMS_PER_PRODUCER);
+    assert_eq!(queue.dequeue(&mut queue.new_lock(0)), None);
+}
+
+pub fn sub_queue_multi_thread_batches_no_loss<S: ConcurrentSubQueue<usize> + Sync>(queue: S) {
+    let collected = produce_consume(
+        |stream| (queue.new_lock(stream), Vec::new()),
+        |(lock, batch), item| {
+            batch.push(item);
+            if batch.len() == BATCH_SIZE || item % ITEMS_PER_PRODUCER == ITEMS_PER_PRODUCER - 1 {
+                queue.enqueue_batch(std::mem::take(batch), lock);
+            }
+        },
+        |(lock, batch)| {
+            if batch.is_empty() {
+                *batch = queue.dequeue_batch(BATCH_SIZE, lock);
+                batch.reverse();
+            }
+            batch.pop()
+        },
+        |p, i| p * ITEMS_PER_PRODUCER + i,
+    );
+    assert_all_exactly_once(collected.concat(), PRODUCERS * ITEMS_PER_PRODUCER);
+    assert_eq!(queue.dequeue_batch(BATCH_SIZE, &mut queue.new_lock(0)), []);
+}
+
+pub fn sub_queue_per_producer_order<S: ConcurrentSubQueue<(usize, usize)> + Sync>(queue: S) {
+    let collected = produce_consume(
+        |stream| queue.new_lock(stream),
+        |lock, item| queue.enqueue(item, lock),
+        |lock| queue.dequeue(lock),
+        |p, i| (p, i),
+    );
+    assert_per_producer_order(collected);
+}
+
+pub fn sub_queue_drops_payloads<S: ConcurrentSubQueue<Tracked>>(queue: S) {
+    let tracker = DropTracker::new(SINGLE_THREAD_ITEMS);
+    let mut lock = queue.new_lock(0);
+    for id in 0..SINGLE_THREAD_ITEMS {
+        queue.enqueue(tracker.item(id), &mut lock);
+    }
+    let dequeued: Vec<_> = (0..SINGLE_THREAD_ITEMS / 2)
+        .filter_map(|_| queue.dequeue(&mut lock))
+        .collect();
+    for item in &dequeued {
+        assert_eq!(tracker.drops(item.id), 0, "dequeued item already dropped");
+    }
+    drop(dequeued);
+    drop(lock);
+    drop(queue);
+    tracker.assert_all_dropped_once();
+}
+
+/// Instantiates the conformance tests for a [`ConcurrentQueue`], given as an expression creating
+/// a new queue. Strict queues are additionally tested for FIFO order.
+macro_rules! queue_conformance_tests {
+    (relaxed, $new_queue:expr) => {
+        #[test]
+        fn conformance_single_thread_no_loss() {
+            $crate::conformance::single_thread_no_loss($new_queue);
+        }
+
+        #[test]
+        fn conformance_multi_thread_no_loss() {
+            $crate::conformance::multi_thread_no_loss($new_queue);
+        }
+
+        #[test]
+        fn conformance_drops_payloads() {
+            $crate::conformance::drops_payloads($new_queue);
+        }
+
+        #[test]
+        fn conformance_multi_thread_batches_no_loss() {
+            $crate::conformance::multi_thread_batches_no_loss($new_queue);
+        }
+    };
+    (strict, $new_queue:expr) => {
+        $crate::conformance::queue_conformance_tests!(relaxed, $new_queue);
+
+        #[test]
+        fn conformance_single_thread_fifo() {
+            $crate::conformance::single_thread_fifo($new_queue);
+        }
+
+        #[test]
+        fn conformance_single_thread_batch_fifo() {
+            $crate::conformance::single_thread_batch_fifo($new_queue);
+        }
+
+        #[test]
+        fn conformance_per_producer_order() {
+            $crate::conformance::per_producer_order($new_queue);
+        }
+    };
+}
+
+/// Instantiates the conformance tests for a [`ConcurrentSubQueue`], given as an expression
+/// creating a new queue. Strict sub-queues are additionally tested for FIFO order.
+macro_rules! sub_queue_conformance_tests {
+    (relaxed, $new_queue:expr) => {
+        #[test]
+        fn sub_queue_conformance_single_thread_no_loss() {
+            $crate::conformance::sub_queue_single_thread_no_loss($new_queue);
+        }
+
+        #[test]
+        fn sub_queue_conformance_multi_thread_no_loss() {
+            $crate::conformance::sub_queue_multi_thread_no_loss($new_queue);
+        }
+
+        #[test]
+        fn sub_queue_conformance_drops_payloads() {
+            $crate::conformance::sub_queue_drops_payloads($new_queue);
+        }
+
+        #[test]
+        fn sub_queue_conformance_multi_thread_batches_no_loss() {
+            $crate::conformance::sub_queue_multi_thread_batches_no_loss($new_queue);
+        }
+    };
+    (strict, $new_queue:expr) => {
+        $crate::conformance::sub_queue_conformance_tests!(relaxed, $new_queue);
+
+        #[test]
+        fn sub_queue_conformance_single_thread_fifo() {
+            $crate::conformance::sub_queue_single_thread_fifo($new_queue);
+        }
+
+        #[test]
+        fn sub_queue_conformance_single_thread_batch_fifo() {
+            $crate::conformance::sub_queue_single_thread_batch_fifo($new_queue);
+        }
+
+        #[test]
+        fn sub_queue_conformance_per_producer_order() {
+            $crate::conformance::sub_queue_per_producer_order($new_queue);
+        }
+    };
+}
+
+pub(crate) use {queue_conformance_tests, sub_queue_conformance_tests};
@@ -0,0 +1,118 @@
+//! Lets threads and tasks wait until something happens, without a lock on the notifying side
+//! while no one waits.
+//!
+//! A waiter announces itself with [`prepare_wait`](EventCount::prepare_wait), checks its
+//! condition once more, and only then registers a [`Waker`], which is woken as soon as the epoch
+//! moved past the one it saw. Threads wait with [`wait`](EventCount::wait), through a waker which
+//! unparks them.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{fence, AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    task::{Wake, Waker},
+    thread::{self, Thread},
+    time::Instant,
+};
+
+pub(crate) struct EventCount {
+    epoch: AtomicUsize,
+    waiters: AtomicUsize,
+    /// The registered wakers by the id of their waiter
+    wakers: Mutex<HashMap<usize, Waker>>,
+    next_id: AtomicUsize,
+}
+
+impl EventCount {
+    pub(crate) fn new() -> Self {
+        Self {
+            epoch: AtomicUsize::new(0),
+            waiters: AtomicUsize::new(0),
+            wakers: Mutex::new(HashMap::new()),
+            next_id: AtomicUsize::new(0),
+        }
+    }
+
+    /// A new id to register a waker under, one for every handle which can wait.
+    pub(crate) fn new_id(&self) -> usize {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Announces a waiter, returning the epoch to wait past. Every call is ended by a
+    /// [`cancel_wait`](Self::cancel_wait).
+    pub(crate) fn prepare_wait(&self) -> usize {
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        // Pairs with the fence in notify: either the notifier sees this waiter, or the condition
+        // checked after this sees what the notifier did
+        fence(Ordering::SeqCst);
+        self.epoch.load(Ordering::SeqCst)
+    }
+
+    /// Registers the waker to be woken once the epoch moves past `key`, replacing an earlier one
+    /// of the same id. Returns false without registering it if the epoch already moved.
+    pub(crate) fn register(&self, id: usize, key: usize, waker: &Waker) -> bool {
+        let mut wakers = self.wakers.lock().unwrap_or_else(PoisonError::into_inner);
+        // Checked under the lock, so a notifier either moved the epoch before this check, or
+        // takes the wakers after this registration
+        if self.epoch.load(Ordering::SeqCst) != key {
+            return false;
+        }
+        wakers.insert(id, waker.clone());
+        true
+    }
+
+    /// Unregisters the waiter, whether or not it was woken.
+    pub(crate) fn cancel_wait(&self, id: usize) {
+        self.wakers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&id);
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Parks the thread until the epoch moves past `key` or until `deadline`, and unregisters
+    /// the waiter.
+    pub(crate) fn wait(&self, id: usize, key: usize, deadline: Instant) {
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        if self.register(id, key, &waker) {
+            // Unparks can be spurious or left over from earlier waits, so only the epoch counts
+            while self.epoch.load(Ordering::SeqCst) == key {
+                let Some(timeout) = deadline.checked_duration_since(Instant::now()) else {
+                    break;
+                };
+                thread::park_timeout(timeout);
+            }
+        }
+        self.cancel_wait(id);
+    }
+
+    /// Wakes all waiters, which is a single load if there are none.
+    pub(crate) fn notify(&self) {
+        fence(Ordering::SeqCst);
+        if self.waiters.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+        let wakers: Vec<Waker> = {
+            let mut wakers = self.wakers.lock().unwrap_or_else(PoisonError::into_inner);
+            wakers.drain().map(|(_, waker)| waker).collect()
+        };
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn waiters(&self) -> usize {
+        self.waiters.load(Ordering::SeqCst)
+    }
+}
+
+/// Wakes a thread parked in [`EventCount::wait`].
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
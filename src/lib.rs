@@ -1,8 +1,22 @@
+pub mod apps;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod blocking;
+pub mod checker;
+#[cfg(all(test, not(loom)))]
+mod conformance;
+mod eventcount;
+pub mod owned;
+pub mod padded;
+pub mod registry;
 pub mod relaxed_queues;
 pub mod strict_queues;
+mod sync;
 
 pub trait QueueType {}
 
+/// Queues which can dequeue items out of order. Their dequeues can also find nothing while they
+/// hold items, in the places they did not look, so use [`drain`] to empty them.
 pub struct Relaxed;
 impl QueueType for Relaxed {}
 pub struct Strict;
@@ -10,12 +24,130 @@ impl QueueType for Strict {}
 
 pub trait ConcurrentQueue<T> {
     type QueueType: QueueType;
+    /// The thread handle of the queue, borrowing it for `'q`. Naming it lets handles be stored in
+    /// structs or wrapped by other handles.
+    type Handle<'q>: Handle<T>
+    where
+        Self: 'q;
+
     /// Returns a thread handle to the queue, which can be used for enqueues and dequeues
-    fn register(&self) -> impl Handle<T>;
+    fn register(&self) -> Self::Handle<'_>;
+
+    /// Like [`register`](Self::register), but relaxed queues take the random choices of the
+    /// handle from the given stream instead of the next one. Seeded queues then make the same
+    /// choices for the same streams, whichever order the handles register in.
+    fn register_with_stream(&self, stream: usize) -> Self::Handle<'_> {
+        let _ = stream;
+        self.register()
+    }
+}
+
+/// What a handle did, as far as its queue keeps track of it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HandleStats {
+    /// Dequeues which fell back to searching more of the queue than the place they looked first
+    pub retried_dequeues: usize,
 }
 
 pub trait Handle<T> {
     fn enqueue(&mut self, item: T);
 
     fn dequeue(&mut self) -> Option<T>;
+
+    /// Enqueues the items in order. Queues which can add a whole batch at once do so, instead of
+    /// synchronizing once per item.
+    fn enqueue_batch(&mut self, items: Vec<T>) {
+        for item in items {
+            self.enqueue(item);
+        }
+    }
+
+    /// Dequeues up to `max` items, stopping at the first dequeue which finds the queue empty.
+    fn dequeue_batch(&mut self, max: usize) -> Vec<T> {
+        (0..max).map_while(|_| self.dequeue()).collect()
+    }
+
+    /// What the handle did so far. Queues which keep no statistics report zeros.
+    fn stats(&self) -> HandleStats {
+        HandleStats::default()
+    }
+}
+
+/// How many dequeues in a row have to find nothing before [`drain`] counts a queue as empty.
+const DRAIN_MISSES: usize = 1000;
+
+/// Dequeues until the queue is empty, returning the dequeued items, once no thread enqueues
+/// anymore. A single empty dequeue does not mean that a [`Relaxed`] queue is empty, so this only
+/// stops once many in a row found nothing.
+pub fn drain<T>(mut dequeue: impl FnMut() -> Option<T>) -> Vec<T> {
+    let mut items = Vec::new();
+    let mut misses = 0;
+    while misses < DRAIN_MISSES {
+        match dequeue() {
+            Some(item) => {
+                items.push(item);
+                misses = 0;
+            }
+            None => misses += 1,
+        }
+    }
+    items
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use std::{cell::RefCell, sync::LazyLock};
+
+    use crate::{
+        relaxed_queues::dra_queue::DRaQueue,
+        strict_queues::{countable_wrapper::CountableWrapper, ms::MSQueue},
+        ConcurrentQueue, Handle,
+    };
+
+    type Queue = DRaQueue<CountableWrapper<MSQueue<usize>>, usize>;
+
+    static QUEUE: LazyLock<Queue> = LazyLock::new(|| Queue::new(4, 2));
+
+    thread_local! {
+        static HANDLE: RefCell<<Queue as ConcurrentQueue<usize>>::Handle<'static>> =
+            RefCell::new(QUEUE.register());
+    }
+
+    /// A handle kept together with how many items it enqueued.
+    struct Counting<'q, Q: ConcurrentQueue<usize> + 'q> {
+        handle: Q::Handle<'q>,
+        enqueued: usize,
+    }
+
+    impl<'q, Q: ConcurrentQueue<usize>> Counting<'q, Q> {
+        fn new(queue: &'q Q) -> Self {
+            Self {
+                handle: queue.register(),
+                enqueued: 0,
+            }
+        }
+    }
+
+    #[test]
+    fn stores_named_handles() {
+        let queue = MSQueue::new();
+        let mut counting = Counting::new(&queue);
+        for i in 0..3 {
+            counting.handle.enqueue(i);
+            counting.enqueued += 1;
+        }
+        assert_eq!(counting.enqueued, 3);
+        assert_eq!(counting.handle.dequeue(), Some(0));
+
+        std::thread::scope(|s| {
+            for i in 0..2 {
+                s.spawn(move || HANDLE.with_borrow_mut(|handle| handle.enqueue(i)));
+            }
+        });
+        let mut items: Vec<_> = (0..100)
+            .filter_map(|_| HANDLE.with_borrow_mut(|handle| handle.dequeue()))
+            .collect();
+        items.sort_unstable();
+        assert_eq!(items, [0, 1]);
+    }
 }
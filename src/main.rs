@@ -5,90 +5,97 @@ use jemallocator::Jemalloc;
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
-use clap::{Parser, Subcommand, ValueEnum};
-use core_affinity::CoreId;
-use std::{
-    sync::{
-        atomic::{AtomicBool, AtomicUsize, Ordering},
-        Barrier,
-    },
-    thread,
-    time::Duration,
-};
+mod benchmark;
+
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand};
+use serde::Serialize;
 
 use relaxed_queues::{
-    relaxed_queues::{dra_queue::DRaQueue, round_robin_queue::RoundRobinQueue},
-    strict_queues::{countable_wrapper::CountableWrapper, ms::MSQueue},
-    ConcurrentQueue, Handle,
+    padded::CACHE_PADDED,
+    registry::{QueueSpec, QueueVisitor, SubQueueSpec},
+    ConcurrentQueue,
+};
+
+use benchmark::{
+    benchmark,
+    output::{Output, OutputFormat, Record},
+    payload::{Payload, PayloadConfig, PayloadKind},
+    perf::Counters,
+    placement::Placement,
+    scenario::ScenarioConfig,
+    sweep::SweepConfig,
+    work::WorkConfig,
+    workload::WorkloadConfig,
+    Run,
 };
 
 fn main() {
     let config = BenchConfig::parse();
-
-    match config.queue {
-        Queue::DraQueue {
-            subqueue,
-            subqueues,
-            choice: d_choice,
-        } => match subqueue {
-            StrictQueue::MSQueue => {
-                let queue = DRaQueue::<CountableWrapper<MSQueue<_>>, _>::new(subqueues, d_choice);
-                benchmark_producer_consumer(queue, config)
-            }
-            StrictQueue::LockFreeQueue => {
-                let queue = DRaQueue::<CountableWrapper<lockfree::queue::Queue<_>>, _>::new(
-                    subqueues, d_choice,
-                );
-                benchmark_producer_consumer(queue, config)
-            }
-            StrictQueue::CrossbeamQueue => {
-                let queue = DRaQueue::<CountableWrapper<crossbeam_queue::SegQueue<_>>, _>::new(
-                    subqueues, d_choice,
-                );
-                benchmark_producer_consumer(queue, config)
-            }
-            StrictQueue::ConcurrentQueue => {
-                let queue =
-                    DRaQueue::<CountableWrapper<concurrent_queue::ConcurrentQueue<_>>, _>::new(
-                        subqueues, d_choice,
-                    );
-                benchmark_producer_consumer(queue, config)
-            }
-        },
-        Queue::RoundRobin {
-            subqueue,
-            subqueues,
-        } => match subqueue {
-            StrictQueue::MSQueue => {
-                let queue = RoundRobinQueue::<MSQueue<_>, _>::new(subqueues);
-                benchmark_producer_consumer(queue, config)
-            }
-            StrictQueue::LockFreeQueue => {
-                let queue = RoundRobinQueue::<lockfree::queue::Queue<_>, _>::new(subqueues);
-                benchmark_producer_consumer(queue, config)
-            }
-            StrictQueue::CrossbeamQueue => {
-                let queue = RoundRobinQueue::<crossbeam_queue::SegQueue<_>, _>::new(subqueues);
-                benchmark_producer_consumer(queue, config)
-            }
-            StrictQueue::ConcurrentQueue => {
-                let queue =
-                    RoundRobinQueue::<concurrent_queue::ConcurrentQueue<_>, _>::new(subqueues);
-                benchmark_producer_consumer(queue, config)
+    if let Err(error) = config.payload.validate() {
+        BenchConfig::command()
+            .error(ErrorKind::InvalidValue, error)
+            .exit();
+    }
+    if let Err(error) = config.check_required() {
+        BenchConfig::command()
+            .error(ErrorKind::MissingRequiredArgument, error)
+            .exit();
+    }
+    if config.perf && Counters::open().is_empty() {
+        eprintln!(
+            "warning: no perf events could be opened, see /proc/sys/kernel/perf_event_paranoid"
+        );
+    }
+    let mut output = Output::new(config.output_format);
+    match &config.command {
+        Command::Queue { queue } => output.write(&run(*queue, &config)),
+        Command::Sweep(sweep) => sweep.run(&config, &mut output),
+        Command::Scenarios(scenarios) => {
+            if let Err(error) = scenarios.run(&config, &mut output) {
+                eprintln!("error: {error}");
+                std::process::exit(2);
             }
-        },
-        Queue::MSQueue => benchmark_producer_consumer(MSQueue::new(), config),
-        Queue::LockFreeQueue => benchmark_producer_consumer(lockfree::queue::Queue::new(), config),
-        Queue::CrossbeamQueue => {
-            benchmark_producer_consumer(crossbeam_queue::SegQueue::new(), config)
-        }
-        Queue::ConcurrentQueue => {
-            benchmark_producer_consumer(concurrent_queue::ConcurrentQueue::unbounded(), config)
         }
+    }
+}
+
+/// Benchmarks a single queue.
+fn run<'c>(queue: QueueSpec, config: &'c BenchConfig) -> Record<'c> {
+    let run = match (config.payload.payload, config.payload.payload_size) {
+        (PayloadKind::I32, _) => run_payload::<i32>(queue, config),
+        (PayloadKind::Inline, 8) => run_payload::<[u8; 8]>(queue, config),
+        (PayloadKind::Inline, 32) => run_payload::<[u8; 32]>(queue, config),
+        (PayloadKind::Inline, 64) => run_payload::<[u8; 64]>(queue, config),
+        (PayloadKind::Inline, 256) => run_payload::<[u8; 256]>(queue, config),
+        (PayloadKind::Inline, 1024) => run_payload::<[u8; 1024]>(queue, config),
+        (PayloadKind::Inline, size) => unreachable!("unsupported inline payload size {size}"),
+        (PayloadKind::Boxed, _) => run_payload::<Box<[u8]>>(queue, config),
+        (PayloadKind::Vec, _) => run_payload::<Vec<u8>>(queue, config),
     };
+    Record::new(config, QueueConfig::new(queue), run)
+}
+
+fn run_payload<P: Payload>(queue: QueueSpec, config: &BenchConfig) -> Run {
+    match config.seed {
+        Some(seed) => queue.visit_seeded::<P, _>(seed, Benchmark { config }),
+        None => queue.visit::<P, _>(Benchmark { config }),
+    }
 }
 
-#[derive(Parser)]
+/// Benchmarks whichever queue the registry builds.
+struct Benchmark<'c> {
+    config: &'c BenchConfig,
+}
+
+impl<P: Payload> QueueVisitor<P> for Benchmark<'_> {
+    type Output = Run;
+
+    fn visit<Q: ConcurrentQueue<P> + Sync>(self, queue: Q) -> Self::Output {
+        benchmark::<P, _>(queue, self.config)
+    }
+}
+
+#[derive(Parser, Serialize, Clone)]
 #[command(version, about, long_about = None)]
 struct BenchConfig {
     /// number of elements to add to the queue before starting the main
@@ -97,126 +104,149 @@ struct BenchConfig {
     prefill: usize,
 
     /// number of threads pushing elements onto the queue.
+    /// Required for a single queue, set for every point by sweep.
     #[arg(short, long)]
-    producer_threads: usize,
+    producer_threads: Option<usize>,
 
     /// number of threads popping elements off the queue.
+    /// Required for a single queue, set for every point by sweep.
     #[arg(short, long)]
-    consumer_threads: usize,
+    consumer_threads: Option<usize>,
 
-    /// duration in seconds to run the test
+    /// duration in seconds to run the test.
+    /// Required for a single queue and sweeps, scenarios can set their own.
     #[arg(short, long)]
-    duration: usize,
-
+    duration: Option<usize>,
+
+    /// record the latency of every operation and report its percentiles.
+    /// Timing the operations lowers the throughput.
+    #[arg(long)]
+    latency: bool,
+
+    /// count cycles, instructions, cache misses and context switches of every
+    /// thread with perf events, falling back to software events where
+    /// hardware ones are unavailable.
+    #[arg(long)]
+    perf: bool,
+
+    /// which CPUs to pin the worker threads to.
+    #[arg(long, value_enum, default_value_t = Placement::Compact)]
+    placement: Placement,
+
+    /// seed for the random choices of relaxed queues, making them
+    /// reproducible for the same placement of work on threads. Random if
+    /// not set.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// how to print the results.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    #[serde(skip)]
+    output_format: OutputFormat,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    workload: WorkloadConfig,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    payload: PayloadConfig,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    work: WorkConfig,
+
+    /// Described by the [`QueueConfig`] of the results instead
     #[command(subcommand)]
-    queue: Queue,
+    #[serde(skip)]
+    command: Command,
 }
 
-#[derive(Clone, Subcommand)]
-enum Queue {
-    DraQueue {
-        /// Which sub-queue do we use?
-        #[arg(long, value_enum)]
-        subqueue: StrictQueue,
-
-        /// The number of sub-queues to use
-        #[arg(short, long)]
-        subqueues: usize,
-
-        /// The number of sub-structures to sample in every operation
-        #[arg(short = 'c', long, default_value_t = 2)]
-        choice: usize,
-    },
-    RoundRobin {
-        /// Which sub-queue do we use?
-        #[arg(long, value_enum)]
-        subqueue: StrictQueue,
-
-        /// The number of sub-queues to use
-        #[arg(short, long)]
-        subqueues: usize,
-    },
-
-    MSQueue,
-    LockFreeQueue,
-    CrossbeamQueue,
-    ConcurrentQueue,
+impl BenchConfig {
+    /// Checks that the options without a default are set, unless the command sets them itself.
+    fn check_required(&self) -> Result<(), String> {
+        match self.command {
+            Command::Queue { .. }
+                if self.producer_threads.is_none()
+                    || self.consumer_threads.is_none()
+                    || self.duration.is_none() =>
+            {
+                Err(
+                    "benchmarking a single queue needs --producer-threads, --consumer-threads and \
+                     --duration"
+                        .to_string(),
+                )
+            }
+            Command::Sweep(_) if self.duration.is_none() => {
+                Err("sweep needs --duration".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The producer and consumer threads, which every command sets before running.
+    fn threads(&self) -> (usize, usize) {
+        self.producer_threads
+            .zip(self.consumer_threads)
+            .expect("the threads are set before running")
+    }
+
+    /// The duration in seconds, which every command sets before running.
+    fn duration(&self) -> usize {
+        self.duration.expect("the duration is set before running")
+    }
 }
 
-#[derive(ValueEnum, Clone, Copy)]
-enum StrictQueue {
-    MSQueue,
-    LockFreeQueue,
-    CrossbeamQueue,
-    ConcurrentQueue,
+#[derive(Clone, Subcommand)]
+enum Command {
+    /// Benchmark a single queue, given as any spec the registry can build,
+    /// such as ms-queue, dra(ms,16,2), rr(crossbeam,8) or rr(dra(ms,8,2),2)
+    Queue { queue: QueueSpec },
+    /// Benchmark every combination of the given queues and parameters several
+    /// times, and summarize the runs of every combination
+    Sweep(SweepConfig),
+    /// Run the named scenarios of a TOML file in sequence
+    Scenarios(ScenarioConfig),
 }
 
-fn benchmark_producer_consumer<C>(queue: C, config: BenchConfig)
-where
-    C: ConcurrentQueue<i32>,
-    for<'a> &'a C: Send,
-{
-    let mut handle = queue.register();
-    for i in 0..config.prefill {
-        handle.enqueue(i as i32);
-    }
+/// A flat description of a [`QueueSpec`], with the same fields for every queue.
+#[derive(Serialize, Clone)]
+struct QueueConfig {
+    name: &'static str,
+    /// The [`QueueSpec`] of the queue
+    spec: String,
+    /// The name of strict sub-queues, or the spec of relaxed ones
+    subqueue: Option<String>,
+    subqueues: Option<usize>,
+    d: Option<usize>,
+    /// Whether the library was built with the `cache-padding` feature
+    cache_padded: bool,
+}
 
-    let done: AtomicBool = AtomicBool::new(false);
-    let enqueues = AtomicUsize::new(0);
-    let dequeues = AtomicUsize::new(0);
-    let barrier = Barrier::new(config.producer_threads + config.consumer_threads + 1);
-
-    let available_cores: Vec<CoreId> =
-        core_affinity::get_core_ids().unwrap_or(vec![CoreId { id: 0 }]);
-    let mut core_iter = available_cores.into_iter().cycle();
-    thread::scope(|s| {
-        // To get move semantict for thread closures
-        let queue = &queue;
-        let enqueues = &enqueues;
-        let dequeues = &dequeues;
-        let done = &done;
-        let barrier = &barrier;
-
-        for _ in 0..config.producer_threads {
-            let core: CoreId = core_iter.next().unwrap();
-            s.spawn(move || {
-                core_affinity::set_for_current(core);
-                let mut local_enqueues = 0;
-                let mut handle = queue.register();
-                barrier.wait();
-                while !done.load(Ordering::Relaxed) {
-                    handle.enqueue(405);
-                    local_enqueues += 1;
-                }
-                enqueues.fetch_add(local_enqueues, Ordering::Relaxed);
-            });
-        }
-        for _ in 0..config.consumer_threads {
-            let core: CoreId = core_iter.next().unwrap();
-            s.spawn(move || {
-                core_affinity::set_for_current(core);
-                let mut local_dequeues = 0;
-                let mut handle = queue.register();
-                barrier.wait();
-                while !done.load(Ordering::Relaxed) {
-                    handle.dequeue();
-                    local_dequeues += 1;
-                }
-                dequeues.fetch_add(local_dequeues, Ordering::Relaxed);
-            });
+impl QueueConfig {
+    fn new(spec: QueueSpec) -> Self {
+        let (name, subqueue, subqueues, d) = match spec {
+            QueueSpec::Strict(kind) => (kind.names()[1], None, None, None),
+            QueueSpec::Relaxed(relaxed) => {
+                let subqueue = match relaxed.subqueue {
+                    SubQueueSpec::Strict(kind) => kind.names()[1].to_string(),
+                    nested => nested.to_string(),
+                };
+                (
+                    relaxed.kind.names()[1],
+                    Some(subqueue),
+                    Some(relaxed.subqueues),
+                    relaxed.d,
+                )
+            }
+        };
+        Self {
+            name,
+            spec: spec.to_string(),
+            subqueue,
+            subqueues,
+            d,
+            cache_padded: CACHE_PADDED,
         }
-
-        barrier.wait();
-        std::thread::sleep(Duration::from_secs(config.duration as u64));
-        done.store(true, Ordering::Relaxed);
-    });
-
-    let enqueues = enqueues.into_inner();
-    let dequeues = dequeues.into_inner();
-    println!(
-        "throughput: {}",
-        (enqueues + dequeues) as f64 / config.duration as f64
-    );
-    println!("number of enqueues: {}", enqueues);
-    println!("number of dequeues: {}", dequeues);
+    }
 }
@@ -0,0 +1,143 @@
+//! Handles which own their queue, for threads and tasks which outlive the scope the queue was
+//! created in.
+
+use std::sync::Arc;
+
+use crate::{ConcurrentQueue, Handle, HandleStats};
+
+/// A handle of any queue which keeps the queue alive through an [`Arc`], so it is `'static`, and
+/// `Send` whenever the borrowing handle of the queue is.
+pub struct OwnedHandle<T, Q: ConcurrentQueue<T> + 'static> {
+    // Declared before the queue so it is dropped before the queue it borrows
+    handle: Q::Handle<'static>,
+    queue: Arc<Q>,
+}
+
+impl<T, Q: ConcurrentQueue<T> + 'static> OwnedHandle<T, Q> {
+    pub fn new(queue: Arc<Q>) -> Self {
+        // SAFETY: the queue lives in the allocation of the Arc, which does not move and is kept
+        // alive by the handle until after the borrowing handle is dropped. The borrow never
+        // leaves this struct, as the handle is only used through its methods.
+        let borrowed: &'static Q = unsafe { &*Arc::as_ptr(&queue) };
+        Self {
+            handle: borrowed.register(),
+            queue,
+        }
+    }
+
+    /// The queue of the handle, to register more handles with.
+    pub fn queue(&self) -> &Arc<Q> {
+        &self.queue
+    }
+}
+
+/// Registers handles which own the queue, on an [`Arc`] of it.
+pub trait ArcQueue<T> {
+    type Queue: ConcurrentQueue<T> + 'static;
+
+    fn register_owned(&self) -> OwnedHandle<T, Self::Queue>;
+}
+
+impl<T, Q: ConcurrentQueue<T> + 'static> ArcQueue<T> for Arc<Q> {
+    type Queue = Q;
+
+    fn register_owned(&self) -> OwnedHandle<T, Q> {
+        OwnedHandle::new(self.clone())
+    }
+}
+
+impl<T, Q: ConcurrentQueue<T> + 'static> Handle<T> for OwnedHandle<T, Q> {
+    fn enqueue(&mut self, item: T) {
+        self.handle.enqueue(item);
+    }
+
+    fn dequeue(&mut self) -> Option<T> {
+        self.handle.dequeue()
+    }
+
+    fn enqueue_batch(&mut self, items: Vec<T>) {
+        self.handle.enqueue_batch(items);
+    }
+
+    fn dequeue_batch(&mut self, max: usize) -> Vec<T> {
+        self.handle.dequeue_batch(max)
+    }
+
+    fn stats(&self) -> HandleStats {
+        self.handle.stats()
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use std::{sync::Arc, thread};
+
+    use crossbeam_queue::SegQueue;
+
+    use crate::{
+        drain,
+        relaxed_queues::{
+            dcbo_queue::DCBOQueue, dra_queue::DRaQueue, round_robin_queue::RoundRobinQueue,
+        },
+        strict_queues::{countable_wrapper::CountableWrapper, ms::MSQueue},
+        ConcurrentQueue, Handle,
+    };
+
+    use super::{ArcQueue, OwnedHandle};
+
+    const ITEMS: usize = if cfg!(miri) { 10 } else { 100 };
+
+    /// Enqueues on a spawned thread, which is not scoped, and dequeues on this one.
+    fn enqueues_on_spawned_thread<Q>(queue: Q)
+    where
+        Q: ConcurrentQueue<usize> + Send + Sync + 'static,
+        OwnedHandle<usize, Q>: Send,
+    {
+        let queue = Arc::new(queue);
+        let mut handle = queue.register_owned();
+        thread::spawn(move || (0..ITEMS).for_each(|i| handle.enqueue(i)))
+            .join()
+            .unwrap();
+
+        let mut handle = queue.register_owned();
+        let mut items = drain(|| handle.dequeue());
+        items.sort_unstable();
+        assert_eq!(items, (0..ITEMS).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn ms_queue() {
+        enqueues_on_spawned_thread(MSQueue::new());
+    }
+
+    #[test]
+    fn crossbeam_queue() {
+        enqueues_on_spawned_thread(SegQueue::new());
+    }
+
+    #[test]
+    #[cfg(not(miri))]
+    fn lockfree_queue() {
+        enqueues_on_spawned_thread(lockfree::queue::Queue::new());
+    }
+
+    #[test]
+    fn concurrent_queue() {
+        enqueues_on_spawned_thread(concurrent_queue::ConcurrentQueue::unbounded());
+    }
+
+    #[test]
+    fn round_robin_queue() {
+        enqueues_on_spawned_thread(RoundRobinQueue::<MSQueue<_>, _>::new(4));
+    }
+
+    #[test]
+    fn dra_queue() {
+        enqueues_on_spawned_thread(DRaQueue::<CountableWrapper<MSQueue<_>>, _>::new(4, 2));
+    }
+
+    #[test]
+    fn dcbo_queue() {
+        enqueues_on_spawned_thread(DCBOQueue::<CountableWrapper<MSQueue<_>>, _>::new(4, 2));
+    }
+}
@@ -0,0 +1,44 @@
+//! Keeps values that different threads write to on separate cache lines.
+
+use std::ops::Deref;
+
+/// Whether [`Padded`] values are aligned to cache lines, which can be turned off by building
+/// without the default `cache-padding` feature, to compare against the unpadded layout.
+pub const CACHE_PADDED: bool = cfg!(feature = "cache-padding");
+
+/// A value on cache lines of its own. Aligned to two cache lines, as modern x86 processors
+/// prefetch cache lines in adjacent pairs.
+#[cfg_attr(feature = "cache-padding", repr(align(128)))]
+#[derive(Debug, Default)]
+pub struct Padded<T>(T);
+
+impl<T> Padded<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> Deref for Padded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use std::mem::align_of;
+
+    use super::{Padded, CACHE_PADDED};
+
+    #[test]
+    fn aligns_only_with_padding() {
+        let expected = if CACHE_PADDED { 128 } else { 1 };
+        assert_eq!(align_of::<Padded<u8>>(), expected);
+
+        let values = [Padded::new(1u8), Padded::new(2)];
+        let distance = &*values[1] as *const u8 as usize - &*values[0] as *const u8 as usize;
+        assert_eq!((distance, *values[1]), (expected, 2));
+    }
+}
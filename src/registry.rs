@@ -0,0 +1,620 @@
+//! Builds queues from descriptions chosen at run time, such as `dra(ms,16,2)`.
+//!
+//! A [`QueueSpec`] names a strict queue, or a relaxed design together with the queue it is built
+//! from and its parameters. The sub-queues of a relaxed design can themselves be relaxed, such as
+//! `rr(dra(ms,8,2),2)`, but only one level deep, as every nesting level is a new type. As
+//! [`ConcurrentQueue`] is generic over its handles, built queues can not be boxed, so they are
+//! instead handed to a [`QueueVisitor`] with their concrete type.
+//!
+//! Every strict queue is registered once in [`StrictKind::visit`], and every relaxed design once
+//! in [`build_relaxed`], after which all combinations can be built.
+//!
+//! Queues are built from a seed, from which every relaxed queue derives the seed of its random
+//! choices and of its relaxed sub-queues.
+
+use std::{fmt, str::FromStr};
+
+use crate::{
+    relaxed_queues::{
+        dcbo_queue::DCBOQueue, derive_seed, dra_queue::DRaQueue, round_robin_queue::RoundRobinQueue,
+    },
+    strict_queues::{
+        countable_wrapper::CountableWrapper, ms::MSQueue, ConcurrentSubQueue, DefaultSubQueue,
+    },
+    ConcurrentQueue,
+};
+
+pub use crate::relaxed_queues::DEFAULT_D;
+
+/// Something to do with a queue whose type is only known at run time.
+pub trait QueueVisitor<T> {
+    type Output;
+
+    fn visit<Q: ConcurrentQueue<T> + Sync>(self, queue: Q) -> Self::Output;
+}
+
+/// Something to do with a strict queue type, which can be used both on its own and as a sub-queue.
+trait StrictVisitor<T> {
+    type Output;
+
+    fn visit<S>(self) -> Self::Output
+    where
+        S: DefaultSubQueue<T> + ConcurrentQueue<T> + Send + Sync;
+}
+
+/// Something to do with a sub-queue type, given a function creating configured sub-queues from a
+/// seed.
+trait SubQueueVisitor<T> {
+    type Output;
+
+    fn visit<S>(self, new: impl Fn(u64) -> S) -> Self::Output
+    where
+        S: ConcurrentSubQueue<T> + Send + Sync;
+}
+
+/// Something to do with a relaxed queue type, which can be used both on its own and as a
+/// sub-queue, given a function creating configured queues from a seed.
+trait RelaxedVisitor<T> {
+    type Output;
+
+    fn visit<R>(self, new: impl Fn(u64) -> R) -> Self::Output
+    where
+        R: ConcurrentSubQueue<T> + ConcurrentQueue<T> + Send + Sync;
+}
+
+/// The strict queues, which can be used on their own or as sub-queues of relaxed designs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StrictKind {
+    MSQueue,
+    LockFreeQueue,
+    CrossbeamQueue,
+    ConcurrentQueue,
+}
+
+/// The relaxed designs, which are built from a number of sub-queues.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelaxedKind {
+    DRaQueue,
+    RoundRobin,
+    DCBOQueue,
+}
+
+impl StrictKind {
+    pub const ALL: [StrictKind; 4] = [
+        StrictKind::MSQueue,
+        StrictKind::LockFreeQueue,
+        StrictKind::CrossbeamQueue,
+        StrictKind::ConcurrentQueue,
+    ];
+
+    /// The name used in specs, followed by the longer name used by the benchmark binary.
+    pub fn names(self) -> [&'static str; 2] {
+        match self {
+            StrictKind::MSQueue => ["ms", "ms-queue"],
+            StrictKind::LockFreeQueue => ["lockfree", "lock-free-queue"],
+            StrictKind::CrossbeamQueue => ["crossbeam", "crossbeam-queue"],
+            StrictKind::ConcurrentQueue => ["concurrent", "concurrent-queue"],
+        }
+    }
+
+    fn visit<T, V>(self, visitor: V) -> V::Output
+    where
+        T: Send + Sync,
+        V: StrictVisitor<T>,
+    {
+        match self {
+            StrictKind::MSQueue => visitor.visit::<MSQueue<T>>(),
+            StrictKind::LockFreeQueue => visitor.visit::<lockfree::queue::Queue<T>>(),
+            StrictKind::CrossbeamQueue => visitor.visit::<crossbeam_queue::SegQueue<T>>(),
+            StrictKind::ConcurrentQueue => visitor.visit::<concurrent_queue::ConcurrentQueue<T>>(),
+        }
+    }
+}
+
+impl RelaxedKind {
+    pub const ALL: [RelaxedKind; 3] = [
+        RelaxedKind::DRaQueue,
+        RelaxedKind::RoundRobin,
+        RelaxedKind::DCBOQueue,
+    ];
+
+    /// The name used in specs, followed by the longer name used by the benchmark binary.
+    pub fn names(self) -> [&'static str; 2] {
+        match self {
+            RelaxedKind::DRaQueue => ["dra", "dra-queue"],
+            RelaxedKind::RoundRobin => ["rr", "round-robin"],
+            RelaxedKind::DCBOQueue => ["dcbo", "dcbo-queue"],
+        }
+    }
+
+    /// Whether the design samples `d` sub-queues in every operation.
+    pub fn takes_d(self) -> bool {
+        match self {
+            RelaxedKind::DRaQueue | RelaxedKind::DCBOQueue => true,
+            RelaxedKind::RoundRobin => false,
+        }
+    }
+}
+
+/// A relaxed design over sub-queues described by `S`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RelaxedSpec<S> {
+    pub kind: RelaxedKind,
+    pub subqueue: S,
+    pub subqueues: usize,
+    /// Only set for designs which [take it](RelaxedKind::takes_d)
+    pub d: Option<usize>,
+}
+
+/// A queue, with everything needed to build it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueueSpec {
+    Strict(StrictKind),
+    Relaxed(RelaxedSpec<SubQueueSpec>),
+}
+
+/// A queue which can be nested in a relaxed design.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubQueueSpec {
+    Strict(StrictKind),
+    Relaxed(RelaxedSpec<StrictKind>),
+}
+
+impl QueueSpec {
+    /// Builds the queue and hands it to the visitor.
+    pub fn visit<T, V>(self, visitor: V) -> V::Output
+    where
+        T: Send + Sync,
+        V: QueueVisitor<T>,
+    {
+        self.visit_seeded(rand::random(), visitor)
+    }
+
+    /// Builds the queue with reproducible random choices, and hands it to the visitor.
+    pub fn visit_seeded<T, V>(self, seed: u64, visitor: V) -> V::Output
+    where
+        T: Send + Sync,
+        V: QueueVisitor<T>,
+    {
+        match self {
+            QueueSpec::Strict(kind) => kind.visit(BuildStrict(visitor)),
+            QueueSpec::Relaxed(spec) => spec.subqueue.visit(BuildRelaxed {
+                spec,
+                visitor: Outermost(visitor, seed),
+            }),
+        }
+    }
+
+    /// Every strict queue, and every relaxed design over every strict queue with the given
+    /// parameters.
+    pub fn all(subqueues: usize, d: usize) -> Vec<QueueSpec> {
+        let strict = StrictKind::ALL.map(QueueSpec::Strict);
+        let relaxed = RelaxedKind::ALL.into_iter().flat_map(|kind| {
+            StrictKind::ALL.map(|subqueue| {
+                QueueSpec::Relaxed(RelaxedSpec::new(
+                    kind,
+                    SubQueueSpec::Strict(subqueue),
+                    subqueues,
+                    d,
+                ))
+            })
+        });
+        strict.into_iter().chain(relaxed).collect()
+    }
+}
+
+impl SubQueueSpec {
+    fn visit<T, V>(self, visitor: V) -> V::Output
+    where
+        T: Send + Sync,
+        V: SubQueueVisitor<T>,
+    {
+        match self {
+            SubQueueSpec::Strict(kind) => kind.visit(Unconfigured(visitor)),
+            SubQueueSpec::Relaxed(spec) => spec.subqueue.visit(Unconfigured(BuildRelaxed {
+                spec,
+                visitor: Nested(visitor),
+            })),
+        }
+    }
+}
+
+impl<S> RelaxedSpec<S> {
+    /// A spec with the given `d`, if the design takes one.
+    pub fn new(kind: RelaxedKind, subqueue: S, subqueues: usize, d: usize) -> Self {
+        Self {
+            kind,
+            subqueue,
+            subqueues,
+            d: kind.takes_d().then_some(d),
+        }
+    }
+}
+
+/// Builds the relaxed design of the spec over sub-queues created by `new`, where every sub-queue
+/// gets its own seed derived from the seed of the design.
+///
+/// This is the only place relaxed designs need to be registered.
+fn build_relaxed<T, S, V>(
+    kind: RelaxedKind,
+    subqueues: usize,
+    d: usize,
+    new: impl Fn(u64) -> S,
+    visitor: V,
+) -> V::Output
+where
+    T: Send + Sync,
+    S: ConcurrentSubQueue<T> + Send + Sync,
+    V: RelaxedVisitor<T>,
+{
+    let new_subqueues = |seed| {
+        (0..subqueues)
+            .map(|i| new(derive_seed(seed, i)))
+            .collect::<Vec<_>>()
+    };
+    let counted = |seed| {
+        new_subqueues(seed)
+            .into_iter()
+            .map(CountableWrapper::from)
+            .collect()
+    };
+    match kind {
+        RelaxedKind::DRaQueue => {
+            visitor.visit(|seed| DRaQueue::<_, _>::from_subqueues(counted(seed), d).with_seed(seed))
+        }
+        RelaxedKind::RoundRobin => {
+            visitor.visit(|seed| RoundRobinQueue::from_subqueues(new_subqueues(seed)))
+        }
+        RelaxedKind::DCBOQueue => visitor
+            .visit(|seed| DCBOQueue::<_, _>::from_subqueues(counted(seed), d).with_seed(seed)),
+    }
+}
+
+/// Hands a strict queue to a [`QueueVisitor`].
+struct BuildStrict<V>(V);
+
+impl<T, V: QueueVisitor<T>> StrictVisitor<T> for BuildStrict<V> {
+    type Output = V::Output;
+
+    fn visit<S>(self) -> Self::Output
+    where
+        S: DefaultSubQueue<T> + ConcurrentQueue<T> + Send + Sync,
+    {
+        self.0.visit(<S as DefaultSubQueue<T>>::new())
+    }
+}
+
+/// Uses strict queues with their default configuration as sub-queues.
+struct Unconfigured<V>(V);
+
+impl<T, V: SubQueueVisitor<T>> StrictVisitor<T> for Unconfigured<V> {
+    type Output = V::Output;
+
+    fn visit<S>(self) -> Self::Output
+    where
+        S: DefaultSubQueue<T> + ConcurrentQueue<T> + Send + Sync,
+    {
+        self.0.visit(|_| <S as DefaultSubQueue<T>>::new())
+    }
+}
+
+/// Builds the relaxed design of the spec over the visited sub-queues.
+struct BuildRelaxed<S, V> {
+    spec: RelaxedSpec<S>,
+    visitor: V,
+}
+
+impl<T: Send + Sync, S, V: RelaxedVisitor<T>> SubQueueVisitor<T> for BuildRelaxed<S, V> {
+    type Output = V::Output;
+
+    fn visit<Q>(self, new: impl Fn(u64) -> Q) -> Self::Output
+    where
+        Q: ConcurrentSubQueue<T> + Send + Sync,
+    {
+        let spec = self.spec;
+        let d = spec.d.unwrap_or(DEFAULT_D);
+        build_relaxed(spec.kind, spec.subqueues, d, new, self.visitor)
+    }
+}
+
+/// Hands a relaxed queue built from the seed to a [`QueueVisitor`].
+struct Outermost<V>(V, u64);
+
+impl<T, V: QueueVisitor<T>> RelaxedVisitor<T> for Outermost<V> {
+    type Output = V::Output;
+
+    fn visit<R>(self, new: impl Fn(u64) -> R) -> Self::Output
+    where
+        R: ConcurrentSubQueue<T> + ConcurrentQueue<T> + Send + Sync,
+    {
+        self.0.visit(new(self.1))
+    }
+}
+
+/// Uses relaxed queues as the sub-queues of another relaxed queue.
+struct Nested<V>(V);
+
+impl<T, V: SubQueueVisitor<T>> RelaxedVisitor<T> for Nested<V> {
+    type Output = V::Output;
+
+    fn visit<R>(self, new: impl Fn(u64) -> R) -> Self::Output
+    where
+        R: ConcurrentSubQueue<T> + ConcurrentQueue<T> + Send + Sync,
+    {
+        self.0.visit(new)
+    }
+}
+
+impl fmt::Display for StrictKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.names()[0])
+    }
+}
+
+impl<S: fmt::Display> fmt::Display for RelaxedSpec<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}({},{}",
+            self.kind.names()[0],
+            self.subqueue,
+            self.subqueues
+        )?;
+        if let Some(d) = self.d {
+            write!(f, ",{d}")?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl fmt::Display for QueueSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueueSpec::Strict(kind) => kind.fmt(f),
+            QueueSpec::Relaxed(spec) => spec.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for SubQueueSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubQueueSpec::Strict(kind) => kind.fmt(f),
+            SubQueueSpec::Relaxed(spec) => spec.fmt(f),
+        }
+    }
+}
+
+impl FromStr for StrictKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let name = name.trim();
+        if name.contains('(') {
+            return Err(format!(
+                "{name} is nested too deeply, the sub-queues of nested queues must be strict"
+            ));
+        }
+        StrictKind::ALL
+            .into_iter()
+            .find(|kind| kind.names().contains(&name))
+            .ok_or_else(|| format!("unknown strict queue {name}"))
+    }
+}
+
+impl FromStr for RelaxedKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        RelaxedKind::ALL
+            .into_iter()
+            .find(|kind| kind.names().contains(&name))
+            .ok_or_else(|| format!("unknown relaxed queue {name}"))
+    }
+}
+
+impl<S: FromStr<Err = String>> FromStr for RelaxedSpec<S> {
+    type Err = String;
+
+    /// Parses a relaxed queue such as `dra(ms,16,2)` or `rr(crossbeam,8)`. The `d` of designs
+    /// which take one defaults to [`DEFAULT_D`].
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let spec = spec.trim();
+        let (name, arguments) = spec
+            .split_once('(')
+            .ok_or_else(|| format!("{spec} is not a relaxed queue"))?;
+        let kind: RelaxedKind = name.trim().parse()?;
+        let arguments = arguments
+            .strip_suffix(')')
+            .ok_or_else(|| format!("{spec} is missing a closing parenthesis"))?;
+        let arguments = split_arguments(arguments);
+        let number = |argument: &str, what: &str| match argument.parse() {
+            Ok(0) | Err(_) => Err(format!("{what} of {spec} must be a positive number")),
+            Ok(number) => Ok(number),
+        };
+        let (subqueue, subqueues, d) = match (kind.takes_d(), arguments.as_slice()) {
+            (true, [subqueue, subqueues]) => (subqueue, subqueues, DEFAULT_D),
+            (true, [subqueue, subqueues, d]) => (subqueue, subqueues, number(d, "d")?),
+            (false, [subqueue, subqueues]) => (subqueue, subqueues, DEFAULT_D),
+            (true, _) => return Err(format!("{name} takes (subqueue, subqueues[, d])")),
+            (false, _) => return Err(format!("{name} takes (subqueue, subqueues)")),
+        };
+        Ok(RelaxedSpec::new(
+            kind,
+            subqueue.parse()?,
+            number(subqueues, "the number of sub-queues")?,
+            d,
+        ))
+    }
+}
+
+/// Splits arguments at the commas which are not inside the parentheses of nested specs.
+fn split_arguments(arguments: &str) -> Vec<&str> {
+    let mut split = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in arguments.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                split.push(arguments[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    split.push(arguments[start..].trim());
+    split
+}
+
+impl FromStr for QueueSpec {
+    type Err = String;
+
+    /// Parses a strict queue such as `ms`, or a relaxed one such as `dra(ms,16,2)`, whose
+    /// sub-queues may be relaxed queues over strict ones, such as `rr(dra(ms,8,2),2)`.
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        if spec.contains('(') {
+            Ok(QueueSpec::Relaxed(spec.parse()?))
+        } else {
+            Ok(QueueSpec::Strict(spec.parse()?))
+        }
+    }
+}
+
+impl FromStr for SubQueueSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        if spec.contains('(') {
+            Ok(SubQueueSpec::Relaxed(spec.parse()?))
+        } else {
+            Ok(SubQueueSpec::Strict(spec.parse()?))
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use crate::{drain, ConcurrentQueue, Handle};
+
+    use super::{QueueSpec, QueueVisitor, RelaxedKind, RelaxedSpec, StrictKind, SubQueueSpec};
+
+    #[test]
+    fn parses_specs() {
+        assert_eq!("ms".parse(), Ok(QueueSpec::Strict(StrictKind::MSQueue)));
+        assert_eq!(
+            "dra(ms,16,2)".parse(),
+            Ok(QueueSpec::Relaxed(RelaxedSpec {
+                kind: RelaxedKind::DRaQueue,
+                subqueue: SubQueueSpec::Strict(StrictKind::MSQueue),
+                subqueues: 16,
+                d: Some(2),
+            }))
+        );
+        assert_eq!(
+            "round-robin( crossbeam-queue, 8 )".parse(),
+            Ok(QueueSpec::Relaxed(RelaxedSpec {
+                kind: RelaxedKind::RoundRobin,
+                subqueue: SubQueueSpec::Strict(StrictKind::CrossbeamQueue),
+                subqueues: 8,
+                d: None,
+            }))
+        );
+        assert_eq!(
+            "rr(dra(ms,8,3),2)".parse(),
+            Ok(QueueSpec::Relaxed(RelaxedSpec {
+                kind: RelaxedKind::RoundRobin,
+                subqueue: SubQueueSpec::Relaxed(RelaxedSpec {
+                    kind: RelaxedKind::DRaQueue,
+                    subqueue: StrictKind::MSQueue,
+                    subqueues: 8,
+                    d: Some(3),
+                }),
+                subqueues: 2,
+                d: None,
+            }))
+        );
+        assert_eq!(
+            "dcbo(lockfree,4)".parse::<QueueSpec>().unwrap().to_string(),
+            "dcbo(lockfree,4,2)"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_specs() {
+        for spec in [
+            "stack",
+            "dra(ms)",
+            "dra(ms,0)",
+            "dra(ms,4,2",
+            "rr(ms,4,2)",
+            "dra(stack,4)",
+            "dcbo(ms,4,-1)",
+            "rr(rr(rr(ms,2),2),2)",
+        ] {
+            assert!(spec.parse::<QueueSpec>().is_err(), "{spec}");
+        }
+    }
+
+    /// Enqueues and dequeues some items on a single thread, returning how many came back.
+    struct RoundTrip;
+
+    impl QueueVisitor<usize> for RoundTrip {
+        type Output = usize;
+
+        fn visit<Q: ConcurrentQueue<usize> + Sync>(self, queue: Q) -> usize {
+            let mut handle = queue.register();
+            for i in 0..100 {
+                handle.enqueue(i);
+            }
+            drain(|| handle.dequeue()).len()
+        }
+    }
+
+    #[test]
+    fn builds_every_combination() {
+        for spec in QueueSpec::all(4, 2) {
+            assert_eq!(spec.to_string().parse(), Ok(spec));
+            if spec.to_string().contains("lockfree") && cfg!(miri) {
+                continue;
+            }
+            assert_eq!(spec.visit(RoundTrip), 100, "{spec}");
+        }
+    }
+
+    #[test]
+    fn builds_nested_queues() {
+        for outer in RelaxedKind::ALL {
+            for inner in RelaxedKind::ALL {
+                let inner = RelaxedSpec::new(inner, StrictKind::MSQueue, 3, 2);
+                let spec =
+                    QueueSpec::Relaxed(RelaxedSpec::new(outer, SubQueueSpec::Relaxed(inner), 2, 2));
+                assert_eq!(spec.to_string().parse(), Ok(spec));
+                assert_eq!(spec.visit(RoundTrip), 100, "{spec}");
+            }
+        }
+    }
+
+    /// The order a single handle dequeues some items in.
+    struct DequeueOrder;
+
+    impl QueueVisitor<usize> for DequeueOrder {
+        type Output = Vec<usize>;
+
+        fn visit<Q: ConcurrentQueue<usize> + Sync>(self, queue: Q) -> Vec<usize> {
+            let mut handle = queue.register();
+            for i in 0..100 {
+                handle.enqueue(i);
+            }
+            (0..100).map_while(|_| handle.dequeue()).collect()
+        }
+    }
+
+    #[test]
+    fn seeds_nested_queues() {
+        let spec: QueueSpec = "dcbo(dra(ms,4,2),4,2)".parse().unwrap();
+        let order = |seed| spec.visit_seeded(seed, DequeueOrder);
+        assert_eq!(order(7), order(7));
+        assert_ne!(order(7), order(8));
+    }
+}
@@ -1,3 +1,49 @@
+use rand::SeedableRng;
+
+use crate::sync::atomic::{AtomicUsize, Ordering};
+
 pub mod dcbo_queue;
 pub mod dra_queue;
 pub mod round_robin_queue;
+
+/// The random number generator relaxed queues use for their random choices by default.
+pub type DefaultRng = rand_pcg::Pcg64Mcg;
+
+/// The number of sub-queues the d-RA and DCBO queues sample when a spec does not give one.
+pub const DEFAULT_D: usize = 2;
+
+/// Derives a separate seed for every stream from a single seed.
+pub(crate) fn derive_seed(seed: u64, stream: usize) -> u64 {
+    // Consecutive streams get seeds far apart, and the generators hash their seed further
+    seed ^ (stream as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+}
+
+/// The random number generators of the handles of a queue, one stream for every handle, which
+/// are reproducible for a given seed.
+pub(crate) struct Streams {
+    seed: u64,
+    next: AtomicUsize,
+}
+
+impl Streams {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Streams from a random seed, for queues which are not seeded.
+    pub(crate) fn random() -> Self {
+        Self::new(rand::random())
+    }
+
+    /// The stream of the next handle registered without one.
+    pub(crate) fn next_stream(&self) -> usize {
+        self.next.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub(crate) fn nth<R: SeedableRng>(&self, stream: usize) -> R {
+        R::seed_from_u64(derive_seed(self.seed, stream))
+    }
+}
@@ -1,97 +1,219 @@
 use std::{marker::PhantomData, mem::MaybeUninit};
 
-use rand::{rngs::ThreadRng, Rng};
+use rand::{Rng, SeedableRng};
 
 use crate::{
-    strict_queues::{ConcurrentSubQueue, CountableVersionedConcurrentSubQueue},
-    ConcurrentQueue, Handle, Relaxed,
+    padded::Padded,
+    strict_queues::{ConcurrentSubQueue, CountableVersionedConcurrentSubQueue, DefaultSubQueue},
+    ConcurrentQueue, Handle, HandleStats, Relaxed,
 };
 
-pub struct DCBOQueue<SubQueue, T> {
-    subqueues: Vec<SubQueue>,
+use super::{DefaultRng, Streams};
+
+pub struct DCBOQueue<SubQueue, T, R = DefaultRng> {
+    subqueues: Vec<Padded<SubQueue>>,
     d: usize,
+    streams: Streams,
     // TODO try the other solution variant:
     // https://users.rust-lang.org/t/dealing-with-unconstrained-type-parameters-in-impl-blocks/49138/3
-    _phantom_data: PhantomData<T>,
+    _phantom_data: PhantomData<(T, R)>,
+}
+
+/// The per-thread state of a [`DCBOQueue`]: the locks of its sub-queues and the random choices.
+pub struct DcboLock<L, R> {
+    lock: L,
+    rng: R,
+    retried_dequeues: usize,
 }
 
-impl<T, S: CountableVersionedConcurrentSubQueue<T>> DCBOQueue<S, T> {
-    fn enqueue(&self, lock: &mut S::LockType, rng: &mut ThreadRng, item: T) {
-        let queue = (0..self.d)
+impl<T, S: CountableVersionedConcurrentSubQueue<T>, R: Rng + SeedableRng> DCBOQueue<S, T, R> {
+    /// The sub-queue with the fewest enqueues of `d` random ones.
+    fn enqueue_queue(&self, lock: &mut DcboLock<S::LockType, R>) -> &S {
+        let rng = &mut lock.rng;
+        (0..self.d)
             .map(|_| rng.gen_range(0..self.subqueues.len()))
-            .map(|i| &self.subqueues[i])
+            .map(|i| &*self.subqueues[i])
             .min_by_key(|q| q.enq_count())
-            .expect("should contain at least one queue");
-        queue.enqueue(item, lock);
+            .expect("should contain at least one queue")
     }
 
-    fn dequeue(&self, lock: &mut S::LockType, rng: &mut ThreadRng) -> Option<T> {
-        let queue_index = (0..self.d)
+    /// The index of the sub-queue with the most dequeues of `d` random ones.
+    fn dequeue_index(&self, lock: &mut DcboLock<S::LockType, R>) -> usize {
+        let rng = &mut lock.rng;
+        (0..self.d)
             .map(|_| rng.gen_range(0..self.subqueues.len()))
             .max_by_key(|&i| self.subqueues[i].deq_count())
-            .expect("should contain at least one queue");
-        let item = self.subqueues[queue_index].dequeue(lock);
+            .expect("should contain at least one queue")
+    }
+
+    fn enqueue(&self, lock: &mut DcboLock<S::LockType, R>, item: T) {
+        self.enqueue_queue(lock).enqueue(item, &mut lock.lock);
+    }
+
+    fn dequeue(&self, lock: &mut DcboLock<S::LockType, R>) -> Option<T> {
+        let queue_index = self.dequeue_index(lock);
+        let item = self.subqueues[queue_index].dequeue(&mut lock.lock);
         if item.is_some() {
             item
         } else {
-            self.double_collect(queue_index, lock)
+            lock.retried_dequeues += 1;
+            self.double_collect(queue_index, &mut lock.lock)
+                .map(|(_, item)| item)
+        }
+    }
+
+    /// Puts the whole batch into a single sub-queue.
+    fn enqueue_batch(&self, lock: &mut DcboLock<S::LockType, R>, items: Vec<T>) {
+        self.enqueue_queue(lock)
+            .enqueue_batch(items, &mut lock.lock);
+    }
+
+    /// Takes the whole batch from a single sub-queue, falling back to a double collect and the rest
+    /// of the batch from the sub-queue it found an item in if it is empty.
+    fn dequeue_batch(&self, lock: &mut DcboLock<S::LockType, R>, max: usize) -> Vec<T> {
+        let queue_index = self.dequeue_index(lock);
+        let items = self.subqueues[queue_index].dequeue_batch(max, &mut lock.lock);
+        if !items.is_empty() || max == 0 {
+            items
+        } else {
+            lock.retried_dequeues += 1;
+            let Some((index, item)) = self.double_collect(queue_index, &mut lock.lock) else {
+                return Vec::new();
+            };
+            let mut items = vec![item];
+            items.extend(self.subqueues[index].dequeue_batch(max - 1, &mut lock.lock));
+            items
         }
     }
 }
 
-struct DCBOQueueHandle<'queue, S: CountableVersionedConcurrentSubQueue<T>, T> {
-    queue: &'queue DCBOQueue<S, T>,
-    lock: S::LockType,
-    thread_rng: ThreadRng,
+pub struct DCBOQueueHandle<'queue, S: CountableVersionedConcurrentSubQueue<T>, T, R> {
+    queue: &'queue DCBOQueue<S, T, R>,
+    lock: DcboLock<S::LockType, R>,
 }
 
-impl<S: CountableVersionedConcurrentSubQueue<T>, T> Handle<T> for DCBOQueueHandle<'_, S, T> {
+impl<S: CountableVersionedConcurrentSubQueue<T>, T, R: Rng + SeedableRng> Handle<T>
+    for DCBOQueueHandle<'_, S, T, R>
+{
     fn enqueue(&mut self, item: T) {
-        self.queue
-            .enqueue(&mut self.lock, &mut self.thread_rng, item);
+        self.queue.enqueue(&mut self.lock, item);
     }
 
     fn dequeue(&mut self) -> Option<T> {
-        self.queue.dequeue(&mut self.lock, &mut self.thread_rng)
+        self.queue.dequeue(&mut self.lock)
+    }
+
+    fn enqueue_batch(&mut self, items: Vec<T>) {
+        self.queue.enqueue_batch(&mut self.lock, items);
+    }
+
+    fn dequeue_batch(&mut self, max: usize) -> Vec<T> {
+        self.queue.dequeue_batch(&mut self.lock, max)
+    }
+
+    fn stats(&self) -> HandleStats {
+        HandleStats {
+            retried_dequeues: self.lock.retried_dequeues,
+        }
     }
 }
 
-impl<T, S: CountableVersionedConcurrentSubQueue<T>> ConcurrentQueue<T> for DCBOQueue<S, T> {
+impl<T, S: CountableVersionedConcurrentSubQueue<T>, R: Rng + SeedableRng> ConcurrentQueue<T>
+    for DCBOQueue<S, T, R>
+{
     type QueueType = Relaxed;
+    type Handle<'q>
+        = DCBOQueueHandle<'q, S, T, R>
+    where
+        Self: 'q;
 
-    fn register(&self) -> impl Handle<T> {
+    fn register(&self) -> DCBOQueueHandle<'_, S, T, R> {
+        self.register_with_stream(self.streams.next_stream())
+    }
+
+    fn register_with_stream(&self, stream: usize) -> DCBOQueueHandle<'_, S, T, R> {
         DCBOQueueHandle {
             queue: self,
-            lock: S::new_lock(),
-            // TODO make deterministic seeding possible? (is this even useful)
-            thread_rng: rand::thread_rng(),
+            lock: self.new_lock(stream),
         }
     }
 }
 
-impl<T, S: CountableVersionedConcurrentSubQueue<T>> DCBOQueue<S, T> {
-    pub fn new(queue_count: usize, d: usize) -> Self {
+/// Lets DCBO queues be nested in other relaxed queues, built with [`DCBOQueue::from_subqueues`].
+impl<T, S: CountableVersionedConcurrentSubQueue<T>, R: Rng + SeedableRng> ConcurrentSubQueue<T>
+    for DCBOQueue<S, T, R>
+{
+    type LockType = DcboLock<S::LockType, R>;
+
+    /// A single lock is used with all sub-queues, so the first one creates it.
+    fn new_lock(&self, stream: usize) -> Self::LockType {
+        DcboLock {
+            lock: self.subqueues[0].new_lock(stream),
+            rng: self.streams.nth(stream),
+            retried_dequeues: 0,
+        }
+    }
+
+    fn enqueue(&self, item: T, lock: &mut Self::LockType) {
+        self.enqueue(lock, item)
+    }
+
+    fn dequeue(&self, lock: &mut Self::LockType) -> Option<T> {
+        self.dequeue(lock)
+    }
+
+    fn enqueue_batch(&self, items: Vec<T>, lock: &mut Self::LockType) {
+        self.enqueue_batch(lock, items)
+    }
+
+    fn dequeue_batch(&self, max: usize, lock: &mut Self::LockType) -> Vec<T> {
+        self.dequeue_batch(lock, max)
+    }
+}
+
+impl<T, S: CountableVersionedConcurrentSubQueue<T>, R> DCBOQueue<S, T, R> {
+    pub fn new(queue_count: usize, d: usize) -> Self
+    where
+        S: DefaultSubQueue<T>,
+    {
+        Self::from_subqueues((0..queue_count).map(|_| S::new()).collect(), d)
+    }
+
+    /// Builds the queue from already created sub-queues, such as configured relaxed queues.
+    pub fn from_subqueues(subqueues: Vec<S>, d: usize) -> Self {
+        assert!(!subqueues.is_empty(), "needs at least one sub-queue");
         Self {
-            subqueues: (0..queue_count).map(|_| S::new()).collect(),
+            subqueues: subqueues.into_iter().map(Padded::new).collect(),
             d,
+            streams: Streams::random(),
             _phantom_data: PhantomData,
         }
     }
 
-    fn double_collect(&self, index: usize, lock: &mut S::LockType) -> Option<T> {
+    /// Makes the random choices reproducible. Every handle gets its own stream derived from the
+    /// seed, the one it is [registered with](ConcurrentQueue::register_with_stream) or else the
+    /// next one in the order they are registered.
+    pub fn with_seed(self, seed: u64) -> Self {
+        Self {
+            streams: Streams::new(seed),
+            ..self
+        }
+    }
+
+    fn double_collect(&self, index: usize, lock: &mut S::LockType) -> Option<(usize, T)> {
         // fallback to checking all queues
         let mut versions = vec![MaybeUninit::uninit(); self.subqueues.len()];
         let mut start_index = index;
         'outer: loop {
-            for queue_index in (start_index..).chain(0..start_index) {
+            for queue_index in (start_index..self.subqueues.len()).chain(0..start_index) {
                 let queue = &self.subqueues[queue_index];
                 versions[queue_index].write(queue.enq_version());
                 if let Some(item) = queue.dequeue(lock) {
-                    return Some(item);
+                    return Some((queue_index, item));
                 }
             }
 
-            for queue_index in (start_index..).chain(0..start_index) {
+            for queue_index in (start_index..self.subqueues.len()).chain(0..start_index) {
                 let queue = &self.subqueues[queue_index];
                 if unsafe { versions[queue_index].assume_init() } != queue.enq_version() {
                     start_index = queue_index;
@@ -102,3 +224,108 @@ impl<T, S: CountableVersionedConcurrentSubQueue<T>> DCBOQueue<S, T> {
         }
     }
 }
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use crate::{
+        conformance::{queue_conformance_tests, sub_queue_conformance_tests},
+        strict_queues::ConcurrentSubQueue,
+        strict_queues::{countable_wrapper::CountableWrapper, ms::MSQueue},
+        sync::schedule,
+        ConcurrentQueue, Handle,
+    };
+
+    use super::DCBOQueue;
+
+    queue_conformance_tests!(
+        relaxed,
+        DCBOQueue::<CountableWrapper<MSQueue<_>>, _>::new(4, 2)
+    );
+    sub_queue_conformance_tests!(
+        relaxed,
+        DCBOQueue::<CountableWrapper<MSQueue<_>>, _>::new(4, 2)
+    );
+
+    /// Two threads enqueue two items each and then dequeue twice, returning what every thread
+    /// dequeued followed by the items left in the queue.
+    fn scheduled_dequeues(seed: u64) -> Vec<Vec<usize>> {
+        let queue = DCBOQueue::<CountableWrapper<MSQueue<_>>, _>::new(2, 2).with_seed(seed);
+        let mut dequeued = schedule::run(
+            seed,
+            (0..2).map(|id| {
+                let queue = &queue;
+                move || {
+                    let mut handle = queue.register();
+                    (0..2).for_each(|i| handle.enqueue(id * 10 + i));
+                    (0..2).filter_map(|_| handle.dequeue()).collect()
+                }
+            }),
+        );
+        let mut handle = queue.register();
+        dequeued.push(std::iter::from_fn(|| handle.dequeue()).collect());
+        dequeued
+    }
+
+    #[test]
+    fn fallback_fills_the_batch() {
+        let queue = DCBOQueue::<CountableWrapper<MSQueue<_>>, _>::new(4, 2);
+        let mut lock = queue.subqueues[0].new_lock(0);
+        queue.subqueues[3].enqueue_batch(vec![1, 2, 3], &mut lock);
+        assert_eq!(queue.register().dequeue_batch(3), [1, 2, 3]);
+    }
+
+    #[test]
+    fn scheduled_interleavings() {
+        schedule::explore(200, |seed| {
+            let dequeues = scheduled_dequeues(seed);
+            let mut items = dequeues.concat();
+            items.sort_unstable();
+            assert_eq!(items, [0, 1, 10, 11]);
+            assert_eq!(
+                dequeues,
+                scheduled_dequeues(seed),
+                "the schedule should replay"
+            );
+        });
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_test {
+    use loom::{model::Builder, sync::Arc, thread};
+
+    use crate::strict_queues::{
+        countable_wrapper::CountableWrapper, ms::MSQueue, ConcurrentSubQueue,
+    };
+
+    use super::DCBOQueue;
+
+    type SubQueue = CountableWrapper<MSQueue<usize>>;
+
+    #[test]
+    fn double_collect_sees_moved_item() {
+        let mut builder = Builder::new();
+        builder.preemption_bound = Some(2);
+        builder.check(|| {
+            let queue = Arc::new(DCBOQueue::<SubQueue, usize>::new(2, 2));
+            let mut lock = queue.subqueues[0].new_lock(0);
+            queue.subqueues[1].enqueue(1, &mut lock);
+
+            // Moves the items from the second sub-queue to the first, so the queue as a whole is
+            // never empty
+            let mover = {
+                let queue = queue.clone();
+                let mut lock = queue.subqueues[0].new_lock(1);
+                thread::spawn(move || {
+                    queue.subqueues[0].enqueue(2, &mut lock);
+                    queue.subqueues[1].dequeue(&mut lock)
+                })
+            };
+
+            let collected = queue.double_collect(0, &mut lock).map(|(_, item)| item);
+            let moved = mover.join().unwrap();
+            assert!(collected.is_some(), "double collect missed both items");
+            assert_ne!(collected, moved);
+        });
+    }
+}
@@ -1,76 +1,215 @@
 use std::marker::PhantomData;
 
-use rand::{rngs::ThreadRng, Rng};
+use rand::{Rng, SeedableRng};
 
 use crate::{
-    strict_queues::{ConcurrentSubQueue, CountableConcurrentSubQueue},
+    padded::Padded,
+    strict_queues::{ConcurrentSubQueue, CountableConcurrentSubQueue, DefaultSubQueue},
     ConcurrentQueue, Handle, Relaxed,
 };
 
-pub struct DRaQueue<SubQueue, T> {
-    subqueues: Vec<SubQueue>,
+use super::{DefaultRng, Streams};
+
+pub struct DRaQueue<SubQueue, T, R = DefaultRng> {
+    subqueues: Vec<Padded<SubQueue>>,
     d: usize,
+    streams: Streams,
     // TODO try the other solution variant:
     // https://users.rust-lang.org/t/dealing-with-unconstrained-type-parameters-in-impl-blocks/49138/3
-    _phantom_data: PhantomData<T>,
+    _phantom_data: PhantomData<(T, R)>,
+}
+
+/// The per-thread state of a [`DRaQueue`]: the locks of its sub-queues and the random choices.
+pub struct DraLock<L, R> {
+    lock: L,
+    rng: R,
 }
 
-impl<T, S: CountableConcurrentSubQueue<T>> DRaQueue<S, T> {
-    fn enqueue(&self, lock: &mut S::LockType, rng: &mut ThreadRng, item: T) {
-        let queue = (0..self.d)
+impl<T, S: CountableConcurrentSubQueue<T>, R: Rng + SeedableRng> DRaQueue<S, T, R> {
+    /// The shortest of `d` random sub-queues.
+    fn enqueue_queue(&self, lock: &mut DraLock<S::LockType, R>) -> &S {
+        let rng = &mut lock.rng;
+        (0..self.d)
             .map(|_| rng.gen_range(0..self.subqueues.len()))
-            .map(|i| &self.subqueues[i])
+            .map(|i| &*self.subqueues[i])
             .min_by_key(|q| q.enq_count().saturating_sub(q.deq_count()))
-            .expect("should contain at least one queue");
-        queue.enqueue(item, lock);
+            .expect("should contain at least one queue")
     }
 
-    fn dequeue(&self, lock: &mut S::LockType, rng: &mut ThreadRng) -> Option<T> {
-        let queue = (0..self.d)
+    /// The longest of `d` random sub-queues.
+    fn dequeue_queue(&self, lock: &mut DraLock<S::LockType, R>) -> &S {
+        let rng = &mut lock.rng;
+        (0..self.d)
             .map(|_| rng.gen_range(0..self.subqueues.len()))
-            .map(|i| &self.subqueues[i])
+            .map(|i| &*self.subqueues[i])
             .max_by_key(|q| q.enq_count().saturating_sub(q.deq_count()))
-            .expect("should contain at least one queue");
-        queue.dequeue(lock)
+            .expect("should contain at least one queue")
+    }
+
+    fn enqueue(&self, lock: &mut DraLock<S::LockType, R>, item: T) {
+        self.enqueue_queue(lock).enqueue(item, &mut lock.lock);
+    }
+
+    fn dequeue(&self, lock: &mut DraLock<S::LockType, R>) -> Option<T> {
+        self.dequeue_queue(lock).dequeue(&mut lock.lock)
+    }
+
+    /// Puts the whole batch into a single sub-queue.
+    fn enqueue_batch(&self, lock: &mut DraLock<S::LockType, R>, items: Vec<T>) {
+        self.enqueue_queue(lock)
+            .enqueue_batch(items, &mut lock.lock);
+    }
+
+    /// Takes the whole batch from a single sub-queue.
+    fn dequeue_batch(&self, lock: &mut DraLock<S::LockType, R>, max: usize) -> Vec<T> {
+        self.dequeue_queue(lock).dequeue_batch(max, &mut lock.lock)
     }
 }
 
-struct DraQueueHandle<'queue, S: CountableConcurrentSubQueue<T>, T> {
-    queue: &'queue DRaQueue<S, T>,
-    lock: S::LockType,
-    thread_rng: ThreadRng,
+pub struct DraQueueHandle<'queue, S: CountableConcurrentSubQueue<T>, T, R> {
+    queue: &'queue DRaQueue<S, T, R>,
+    lock: DraLock<S::LockType, R>,
 }
 
-impl<S: CountableConcurrentSubQueue<T>, T> Handle<T> for DraQueueHandle<'_, S, T> {
+impl<S: CountableConcurrentSubQueue<T>, T, R: Rng + SeedableRng> Handle<T>
+    for DraQueueHandle<'_, S, T, R>
+{
     fn enqueue(&mut self, item: T) {
-        self.queue
-            .enqueue(&mut self.lock, &mut self.thread_rng, item);
+        self.queue.enqueue(&mut self.lock, item);
     }
 
     fn dequeue(&mut self) -> Option<T> {
-        self.queue.dequeue(&mut self.lock, &mut self.thread_rng)
+        self.queue.dequeue(&mut self.lock)
+    }
+
+    fn enqueue_batch(&mut self, items: Vec<T>) {
+        self.queue.enqueue_batch(&mut self.lock, items);
+    }
+
+    fn dequeue_batch(&mut self, max: usize) -> Vec<T> {
+        self.queue.dequeue_batch(&mut self.lock, max)
     }
 }
 
-impl<T, S: CountableConcurrentSubQueue<T>> ConcurrentQueue<T> for DRaQueue<S, T> {
+impl<T, S: CountableConcurrentSubQueue<T>, R: Rng + SeedableRng> ConcurrentQueue<T>
+    for DRaQueue<S, T, R>
+{
     type QueueType = Relaxed;
+    type Handle<'q>
+        = DraQueueHandle<'q, S, T, R>
+    where
+        Self: 'q;
 
-    fn register(&self) -> impl Handle<T> {
+    fn register(&self) -> DraQueueHandle<'_, S, T, R> {
+        self.register_with_stream(self.streams.next_stream())
+    }
+
+    fn register_with_stream(&self, stream: usize) -> DraQueueHandle<'_, S, T, R> {
         DraQueueHandle {
             queue: self,
-            lock: S::new_lock(),
-            // TODO make deterministic seeding possible? (is this even useful)
-            thread_rng: rand::thread_rng(),
+            lock: self.new_lock(stream),
         }
     }
 }
 
-impl<T, S: ConcurrentSubQueue<T>> DRaQueue<S, T> {
-    pub fn new(queue_count: usize, d: usize) -> Self {
+/// Lets d-RA queues be nested in other relaxed queues, built with [`DRaQueue::from_subqueues`].
+impl<T, S: CountableConcurrentSubQueue<T>, R: Rng + SeedableRng> ConcurrentSubQueue<T>
+    for DRaQueue<S, T, R>
+{
+    type LockType = DraLock<S::LockType, R>;
+
+    /// A single lock is used with all sub-queues, so the first one creates it.
+    fn new_lock(&self, stream: usize) -> Self::LockType {
+        DraLock {
+            lock: self.subqueues[0].new_lock(stream),
+            rng: self.streams.nth(stream),
+        }
+    }
+
+    fn enqueue(&self, item: T, lock: &mut Self::LockType) {
+        self.enqueue(lock, item)
+    }
+
+    fn dequeue(&self, lock: &mut Self::LockType) -> Option<T> {
+        self.dequeue(lock)
+    }
+
+    fn enqueue_batch(&self, items: Vec<T>, lock: &mut Self::LockType) {
+        self.enqueue_batch(lock, items)
+    }
+
+    fn dequeue_batch(&self, max: usize, lock: &mut Self::LockType) -> Vec<T> {
+        self.dequeue_batch(lock, max)
+    }
+}
+
+impl<T, S: ConcurrentSubQueue<T>, R> DRaQueue<S, T, R> {
+    pub fn new(queue_count: usize, d: usize) -> Self
+    where
+        S: DefaultSubQueue<T>,
+    {
+        Self::from_subqueues((0..queue_count).map(|_| S::new()).collect(), d)
+    }
+
+    /// Builds the queue from already created sub-queues, such as configured relaxed queues.
+    pub fn from_subqueues(subqueues: Vec<S>, d: usize) -> Self {
+        assert!(!subqueues.is_empty(), "needs at least one sub-queue");
         Self {
-            subqueues: (0..queue_count).map(|_| S::new()).collect(),
+            subqueues: subqueues.into_iter().map(Padded::new).collect(),
             d,
+            streams: Streams::random(),
             _phantom_data: PhantomData,
         }
     }
+
+    /// Makes the random choices reproducible. Every handle gets its own stream derived from the
+    /// seed, the one it is [registered with](ConcurrentQueue::register_with_stream) or else the
+    /// next one in the order they are registered.
+    pub fn with_seed(self, seed: u64) -> Self {
+        Self {
+            streams: Streams::new(seed),
+            ..self
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use crate::{
+        conformance::{queue_conformance_tests, sub_queue_conformance_tests},
+        strict_queues::{countable_wrapper::CountableWrapper, ms::MSQueue},
+        ConcurrentQueue, Handle,
+    };
+
+    use super::DRaQueue;
+
+    queue_conformance_tests!(
+        relaxed,
+        DRaQueue::<CountableWrapper<MSQueue<_>>, _>::new(4, 2)
+    );
+    sub_queue_conformance_tests!(
+        relaxed,
+        DRaQueue::<CountableWrapper<MSQueue<_>>, _>::new(4, 2)
+    );
+
+    /// The order a handle with the given stream dequeues 100 items in, after `registered` other
+    /// handles.
+    fn dequeue_order(seed: u64, stream: usize, registered: usize) -> Vec<i32> {
+        let queue = DRaQueue::<CountableWrapper<MSQueue<_>>, _>::new(8, 2).with_seed(seed);
+        let _others: Vec<_> = (0..registered).map(|_| queue.register()).collect();
+        let mut handle = queue.register_with_stream(stream);
+        for i in 0..100 {
+            handle.enqueue(i);
+        }
+        (0..100).map_while(|_| handle.dequeue()).collect()
+    }
+
+    #[test]
+    fn seeded_queues_are_reproducible() {
+        assert_eq!(dequeue_order(7, 0, 0), dequeue_order(7, 0, 0));
+        assert_ne!(dequeue_order(7, 0, 0), dequeue_order(8, 0, 0));
+        // The stream decides, not how many handles registered before
+        assert_eq!(dequeue_order(7, 1, 0), dequeue_order(7, 1, 3));
+        assert_ne!(dequeue_order(7, 0, 0), dequeue_order(7, 1, 0));
+    }
 }
@@ -1,71 +1,213 @@
 use std::marker::PhantomData;
 
-use crate::{strict_queues::ConcurrentSubQueue, ConcurrentQueue, Handle, Relaxed};
+use crate::{
+    padded::Padded,
+    strict_queues::{ConcurrentSubQueue, DefaultSubQueue},
+    sync::atomic::{AtomicUsize, Ordering},
+    ConcurrentQueue, Handle, HandleStats, Relaxed,
+};
 
 pub struct RoundRobinQueue<SubQueue, T> {
-    subqueues: Vec<SubQueue>,
+    subqueues: Vec<Padded<SubQueue>>,
+    /// The stream of the next handle registered without one, for relaxed sub-queues
+    next_stream: AtomicUsize,
     // TODO try the other solution variant:
     // https://users.rust-lang.org/t/dealing-with-unconstrained-type-parameters-in-impl-blocks/49138/3
     _phantom_data: PhantomData<T>,
 }
 
 impl<T, S: ConcurrentSubQueue<T>> RoundRobinQueue<S, T> {
-    pub fn new(queue_count: usize) -> Self {
+    pub fn new(queue_count: usize) -> Self
+    where
+        S: DefaultSubQueue<T>,
+    {
+        Self::from_subqueues((0..queue_count).map(|_| S::new()).collect())
+    }
+
+    /// Builds the queue from already created sub-queues, such as configured relaxed queues.
+    pub fn from_subqueues(subqueues: Vec<S>) -> Self {
+        assert!(!subqueues.is_empty(), "needs at least one sub-queue");
         Self {
-            subqueues: (0..queue_count).map(|_| S::new()).collect(),
+            subqueues: subqueues.into_iter().map(Padded::new).collect(),
+            next_stream: AtomicUsize::new(0),
             _phantom_data: PhantomData,
         }
     }
-}
 
-impl<S: ConcurrentSubQueue<T>, T> ConcurrentQueue<T> for RoundRobinQueue<S, T> {
-    type QueueType = Relaxed;
+    fn enqueue(&self, lock: &mut RoundRobinLock<S::LockType>, item: T) {
+        lock.inc_cursor(self.subqueues.len());
+        self.subqueues[lock.cursor].enqueue(item, &mut lock.lock);
+    }
 
-    fn register(&self) -> impl Handle<T> {
-        let lock = S::new_lock();
-        RoundRobinQueueHandle {
-            cursor: 0,
-            queue: &self,
-            lock,
+    fn dequeue(&self, lock: &mut RoundRobinLock<S::LockType>) -> Option<T> {
+        lock.inc_cursor(self.subqueues.len());
+        let cursor = lock.cursor;
+        if let Some(item) = self.subqueues[cursor].dequeue(&mut lock.lock) {
+            return Some(item);
         }
+        // fallback to checking all queues
+        lock.retried_dequeues += 1;
+        for queue in self.subqueues[cursor..]
+            .iter()
+            .chain(self.subqueues[0..cursor].iter())
+        {
+            if let Some(item) = queue.dequeue(&mut lock.lock) {
+                return Some(item);
+            }
+        }
+        None
+    }
+
+    /// Puts the whole batch into the next sub-queue in turn.
+    fn enqueue_batch(&self, lock: &mut RoundRobinLock<S::LockType>, items: Vec<T>) {
+        lock.inc_cursor(self.subqueues.len());
+        self.subqueues[lock.cursor].enqueue_batch(items, &mut lock.lock);
+    }
+
+    /// Takes the whole batch from the next sub-queue in turn, or from the first non-empty one.
+    fn dequeue_batch(&self, lock: &mut RoundRobinLock<S::LockType>, max: usize) -> Vec<T> {
+        lock.inc_cursor(self.subqueues.len());
+        let cursor = lock.cursor;
+        let items = self.subqueues[cursor].dequeue_batch(max, &mut lock.lock);
+        if !items.is_empty() || max == 0 {
+            return items;
+        }
+        lock.retried_dequeues += 1;
+        for queue in self.subqueues[cursor..]
+            .iter()
+            .chain(self.subqueues[0..cursor].iter())
+        {
+            let items = queue.dequeue_batch(max, &mut lock.lock);
+            if !items.is_empty() {
+                return items;
+            }
+        }
+        Vec::new()
     }
 }
 
-pub struct RoundRobinQueueHandle<'q, S: ConcurrentSubQueue<T>, T> {
+/// The per-thread state of a [`RoundRobinQueue`]: the locks of its sub-queues and the sub-queue
+/// whose turn it is.
+pub struct RoundRobinLock<L> {
     cursor: usize,
-    queue: &'q RoundRobinQueue<S, T>,
-    lock: S::LockType,
+    lock: L,
+    retried_dequeues: usize,
 }
 
-impl<S: ConcurrentSubQueue<T>, T> RoundRobinQueueHandle<'_, S, T> {
-    fn inc_cursor(&mut self) {
+impl<L> RoundRobinLock<L> {
+    fn inc_cursor(&mut self, queue_count: usize) {
         self.cursor += 1;
-        if self.cursor >= self.queue.subqueues.len() {
+        if self.cursor >= queue_count {
             self.cursor = 0;
         }
     }
 }
 
+impl<S: ConcurrentSubQueue<T>, T> ConcurrentQueue<T> for RoundRobinQueue<S, T> {
+    type QueueType = Relaxed;
+    type Handle<'q>
+        = RoundRobinQueueHandle<'q, S, T>
+    where
+        Self: 'q;
+
+    fn register(&self) -> RoundRobinQueueHandle<'_, S, T> {
+        self.register_with_stream(self.next_stream.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn register_with_stream(&self, stream: usize) -> RoundRobinQueueHandle<'_, S, T> {
+        RoundRobinQueueHandle {
+            queue: self,
+            lock: self.new_lock(stream),
+        }
+    }
+}
+
+pub struct RoundRobinQueueHandle<'q, S: ConcurrentSubQueue<T>, T> {
+    queue: &'q RoundRobinQueue<S, T>,
+    lock: RoundRobinLock<S::LockType>,
+}
+
 impl<S: ConcurrentSubQueue<T>, T> Handle<T> for RoundRobinQueueHandle<'_, S, T> {
     fn enqueue(&mut self, item: T) {
-        self.inc_cursor();
-        self.queue.subqueues[self.cursor].enqueue(item, &mut self.lock);
+        self.queue.enqueue(&mut self.lock, item);
     }
 
     fn dequeue(&mut self) -> Option<T> {
-        self.inc_cursor();
-        if let Some(item) = self.queue.subqueues[self.cursor].dequeue(&mut self.lock) {
-            return Some(item);
+        self.queue.dequeue(&mut self.lock)
+    }
+
+    fn enqueue_batch(&mut self, items: Vec<T>) {
+        self.queue.enqueue_batch(&mut self.lock, items);
+    }
+
+    fn dequeue_batch(&mut self, max: usize) -> Vec<T> {
+        self.queue.dequeue_batch(&mut self.lock, max)
+    }
+
+    fn stats(&self) -> HandleStats {
+        HandleStats {
+            retried_dequeues: self.lock.retried_dequeues,
         }
-        // fallback to checking all queues
-        for queue in self.queue.subqueues[self.cursor..]
-            .iter()
-            .chain(self.queue.subqueues[0..self.cursor].iter())
-        {
-            if let Some(item) = queue.dequeue(&mut self.lock) {
-                return Some(item);
-            }
+    }
+}
+
+/// Lets round-robin queues be nested in other relaxed queues, built with
+/// [`RoundRobinQueue::from_subqueues`].
+impl<S: ConcurrentSubQueue<T>, T> ConcurrentSubQueue<T> for RoundRobinQueue<S, T> {
+    type LockType = RoundRobinLock<S::LockType>;
+
+    /// A single lock is used with all sub-queues, so the first one creates it.
+    fn new_lock(&self, stream: usize) -> Self::LockType {
+        RoundRobinLock {
+            cursor: 0,
+            lock: self.subqueues[0].new_lock(stream),
+            retried_dequeues: 0,
         }
-        None
+    }
+
+    fn enqueue(&self, item: T, lock: &mut Self::LockType) {
+        self.enqueue(lock, item)
+    }
+
+    fn dequeue(&self, lock: &mut Self::LockType) -> Option<T> {
+        self.dequeue(lock)
+    }
+
+    fn enqueue_batch(&self, items: Vec<T>, lock: &mut Self::LockType) {
+        self.enqueue_batch(lock, items)
+    }
+
+    fn dequeue_batch(&self, max: usize, lock: &mut Self::LockType) -> Vec<T> {
+        self.dequeue_batch(lock, max)
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use crate::{
+        conformance::{queue_conformance_tests, sub_queue_conformance_tests},
+        strict_queues::ms::MSQueue,
+        ConcurrentQueue, Handle,
+    };
+
+    use super::RoundRobinQueue;
+
+    queue_conformance_tests!(relaxed, RoundRobinQueue::<MSQueue<_>, _>::new(4));
+    sub_queue_conformance_tests!(relaxed, RoundRobinQueue::<MSQueue<_>, _>::new(4));
+
+    #[test]
+    fn counts_retried_dequeues() {
+        let queue = RoundRobinQueue::<MSQueue<_>, _>::new(2);
+        let mut handle = queue.register();
+        handle.enqueue(1);
+        handle.enqueue(2);
+        // The next sub-queue in turn holds the first item
+        assert_eq!(handle.dequeue(), Some(1));
+        assert_eq!(handle.stats().retried_dequeues, 0);
+        // Both items now sit in the other sub-queue, so the second dequeue has to search
+        handle.enqueue(3);
+        assert_eq!(handle.dequeue(), Some(2));
+        assert_eq!(handle.dequeue(), Some(3));
+        assert_eq!(handle.stats().retried_dequeues, 1);
     }
 }
@@ -1,17 +1,11 @@
 use crate::{ConcurrentQueue, Strict};
 
-use super::ConcurrentSubQueue;
+use super::{ConcurrentSubQueue, DefaultSubQueue};
 
 impl<T: Sync + Send> ConcurrentSubQueue<T> for concurrent_queue::ConcurrentQueue<T> {
     type LockType = ();
 
-    fn new() -> Self {
-        concurrent_queue::ConcurrentQueue::unbounded()
-    }
-
-    fn new_lock() -> Self::LockType {
-        ()
-    }
+    fn new_lock(&self, _stream: usize) -> Self::LockType {}
 
     fn enqueue(&self, item: T, _lock_type: &mut Self::LockType) {
         let _ = self.push(item);
@@ -22,15 +16,19 @@ impl<T: Sync + Send> ConcurrentSubQueue<T> for concurrent_queue::ConcurrentQueue
     }
 }
 
-struct Handle<'q, T> {
+pub struct Handle<'q, T> {
     queue: &'q concurrent_queue::ConcurrentQueue<T>,
 }
 
 impl<T: Sync + Send> ConcurrentQueue<T> for concurrent_queue::ConcurrentQueue<T> {
     type QueueType = Strict;
+    type Handle<'q>
+        = Handle<'q, T>
+    where
+        T: 'q;
 
-    fn register(&self) -> impl crate::Handle<T> {
-        Handle { queue: &self }
+    fn register(&self) -> Handle<'_, T> {
+        Handle { queue: self }
     }
 }
 
@@ -43,3 +41,17 @@ impl<T: Sync + Send> crate::Handle<T> for Handle<'_, T> {
         self.queue.pop().ok()
     }
 }
+
+impl<T: Sync + Send> DefaultSubQueue<T> for concurrent_queue::ConcurrentQueue<T> {
+    fn new() -> Self {
+        concurrent_queue::ConcurrentQueue::unbounded()
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use crate::conformance::{queue_conformance_tests, sub_queue_conformance_tests};
+
+    queue_conformance_tests!(strict, concurrent_queue::ConcurrentQueue::unbounded());
+    sub_queue_conformance_tests!(strict, concurrent_queue::ConcurrentQueue::unbounded());
+}
@@ -1,11 +1,33 @@
-use std::sync::atomic::AtomicUsize;
+use crate::{
+    padded::Padded,
+    sync::{
+        self,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
 
-use super::{ConcurrentSubQueue, CountableConcurrentSubQueue};
+use super::{
+    ConcurrentSubQueue, CountableConcurrentSubQueue, CountableVersionedConcurrentSubQueue,
+    DefaultSubQueue,
+};
 
+/// Counts the operations on a sub-queue. The counters are on separate cache lines, so enqueuers
+/// and dequeuers do not invalidate each other's counter or the queue itself.
 pub struct CountableWrapper<S> {
     queue: S,
-    enq_count: AtomicUsize,
-    deq_count: AtomicUsize,
+    enq_count: Padded<AtomicUsize>,
+    deq_count: Padded<AtomicUsize>,
+}
+
+/// Counts the operations on an already created queue, such as a configured relaxed queue.
+impl<S> From<S> for CountableWrapper<S> {
+    fn from(queue: S) -> Self {
+        Self {
+            queue,
+            enq_count: Padded::new(AtomicUsize::new(0)),
+            deq_count: Padded::new(AtomicUsize::new(0)),
+        }
+    }
 }
 
 impl<S, T> ConcurrentSubQueue<T> for CountableWrapper<S>
@@ -14,33 +36,52 @@ where
 {
     type LockType = S::LockType;
 
-    fn new() -> Self {
-        Self {
-            queue: S::new(),
-            enq_count: 0.into(),
-            deq_count: 0.into(),
-        }
-    }
-
-    fn new_lock() -> Self::LockType {
-        S::new_lock()
+    fn new_lock(&self, stream: usize) -> Self::LockType {
+        self.queue.new_lock(stream)
     }
 
+    /// Counts the item only once it is in the queue, so a changed count always means that the
+    /// item can be found, see [`enq_version`](CountableVersionedConcurrentSubQueue::enq_version).
     fn enqueue(&self, item: T, lock_type: &mut Self::LockType) {
-        self.enq_count
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-        self.queue.enqueue(item, lock_type)
+        self.queue.enqueue(item, lock_type);
+        sync::yield_point();
+        self.enq_count.fetch_add(1, Ordering::Release);
     }
 
     fn dequeue(&self, lock_type: &mut Self::LockType) -> Option<T> {
         if let Some(item) = self.queue.dequeue(lock_type) {
-            self.deq_count
-                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            sync::yield_point();
+            self.deq_count.fetch_add(1, Ordering::Relaxed);
             Some(item)
         } else {
             None
         }
     }
+
+    fn enqueue_batch(&self, items: Vec<T>, lock_type: &mut Self::LockType) {
+        let len = items.len();
+        self.queue.enqueue_batch(items, lock_type);
+        sync::yield_point();
+        self.enq_count.fetch_add(len, Ordering::Release);
+    }
+
+    fn dequeue_batch(&self, max: usize, lock_type: &mut Self::LockType) -> Vec<T> {
+        let items = self.queue.dequeue_batch(max, lock_type);
+        if !items.is_empty() {
+            sync::yield_point();
+            self.deq_count.fetch_add(items.len(), Ordering::Relaxed);
+        }
+        items
+    }
+}
+
+impl<S, T> DefaultSubQueue<T> for CountableWrapper<S>
+where
+    S: DefaultSubQueue<T>,
+{
+    fn new() -> Self {
+        S::new().into()
+    }
 }
 
 impl<S, T> CountableConcurrentSubQueue<T> for CountableWrapper<S>
@@ -48,10 +89,85 @@ where
     S: ConcurrentSubQueue<T>,
 {
     fn enq_count(&self) -> usize {
-        self.enq_count.load(std::sync::atomic::Ordering::Relaxed)
+        sync::yield_point();
+        self.enq_count.load(Ordering::Relaxed)
     }
 
     fn deq_count(&self) -> usize {
-        self.deq_count.load(std::sync::atomic::Ordering::Relaxed)
+        sync::yield_point();
+        self.deq_count.load(Ordering::Relaxed)
+    }
+}
+
+impl<S, T> CountableVersionedConcurrentSubQueue<T> for CountableWrapper<S>
+where
+    S: ConcurrentSubQueue<T>,
+{
+    /// The enqueue count, which only grows. Acquiring it makes the items it counts visible to
+    /// the dequeues which follow.
+    fn enq_version(&self) -> usize {
+        sync::yield_point();
+        self.enq_count.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use crossbeam_queue::SegQueue;
+
+    use crate::{conformance::sub_queue_conformance_tests, strict_queues::ms::MSQueue};
+
+    use super::CountableWrapper;
+
+    mod ms {
+        use super::*;
+
+        sub_queue_conformance_tests!(strict, CountableWrapper::from(MSQueue::new()));
+    }
+
+    mod crossbeam {
+        use super::*;
+
+        sub_queue_conformance_tests!(strict, CountableWrapper::from(SegQueue::new()));
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_test {
+    use loom::{sync::Arc, thread};
+
+    use crate::strict_queues::{
+        ms::MSQueue, ConcurrentSubQueue, CountableConcurrentSubQueue,
+        CountableVersionedConcurrentSubQueue, DefaultSubQueue,
+    };
+
+    use super::CountableWrapper;
+
+    type SubQueue = CountableWrapper<MSQueue<usize>>;
+
+    #[test]
+    fn counts_match_operations() {
+        loom::model(|| {
+            let queue = Arc::new(SubQueue::new());
+            let mut lock = queue.new_lock(0);
+            let producer = {
+                let queue = queue.clone();
+                let mut lock = queue.new_lock(1);
+                thread::spawn(move || {
+                    queue.enqueue(1, &mut lock);
+                })
+            };
+
+            let version = queue.enq_version();
+            let item = queue.dequeue(&mut lock);
+            if version == 1 {
+                // The enqueue is counted after the item becomes visible
+                assert_eq!(item, Some(1));
+            }
+            producer.join().unwrap();
+
+            assert_eq!(queue.enq_version(), 1);
+            assert_eq!(queue.deq_count(), usize::from(item.is_some()));
+        });
     }
 }
@@ -2,7 +2,7 @@ use crossbeam_queue::SegQueue;
 
 use crate::{ConcurrentQueue, Strict};
 
-use super::ConcurrentSubQueue;
+use super::{ConcurrentSubQueue, DefaultSubQueue};
 
 pub struct Handle<'a, T> {
     queue: &'a SegQueue<T>,
@@ -20,22 +20,20 @@ impl<'a, T> crate::Handle<T> for Handle<'a, T> {
 
 impl<T> ConcurrentQueue<T> for SegQueue<T> {
     type QueueType = Strict;
+    type Handle<'q>
+        = Handle<'q, T>
+    where
+        T: 'q;
 
-    fn register(&self) -> impl crate::Handle<T> {
-        Handle { queue: &self }
+    fn register(&self) -> Handle<'_, T> {
+        Handle { queue: self }
     }
 }
 
 impl<T> ConcurrentSubQueue<T> for SegQueue<T> {
     type LockType = ();
 
-    fn new() -> Self {
-        SegQueue::new()
-    }
-
-    fn new_lock() -> Self::LockType {
-        ()
-    }
+    fn new_lock(&self, _stream: usize) -> Self::LockType {}
 
     fn enqueue(&self, item: T, _lock_type: &mut Self::LockType) {
         self.push(item)
@@ -45,3 +43,19 @@ impl<T> ConcurrentSubQueue<T> for SegQueue<T> {
         self.pop()
     }
 }
+
+impl<T> DefaultSubQueue<T> for SegQueue<T> {
+    fn new() -> Self {
+        SegQueue::new()
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use crossbeam_queue::SegQueue;
+
+    use crate::conformance::{queue_conformance_tests, sub_queue_conformance_tests};
+
+    queue_conformance_tests!(strict, SegQueue::new());
+    sub_queue_conformance_tests!(strict, SegQueue::new());
+}
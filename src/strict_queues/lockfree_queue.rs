@@ -2,7 +2,7 @@ use lockfree::queue::Queue;
 
 use crate::{ConcurrentQueue, Strict};
 
-use super::ConcurrentSubQueue;
+use super::{ConcurrentSubQueue, DefaultSubQueue};
 
 pub struct Handle<'a, T> {
     queue: &'a Queue<T>,
@@ -20,22 +20,20 @@ impl<'a, T> crate::Handle<T> for Handle<'a, T> {
 
 impl<T> ConcurrentQueue<T> for Queue<T> {
     type QueueType = Strict;
+    type Handle<'q>
+        = Handle<'q, T>
+    where
+        T: 'q;
 
-    fn register(&self) -> impl crate::Handle<T> {
-        Handle { queue: &self }
+    fn register(&self) -> Handle<'_, T> {
+        Handle { queue: self }
     }
 }
 
 impl<T> ConcurrentSubQueue<T> for Queue<T> {
     type LockType = ();
 
-    fn new() -> Self {
-        Queue::new()
-    }
-
-    fn new_lock() -> Self::LockType {
-        ()
-    }
+    fn new_lock(&self, _stream: usize) -> Self::LockType {}
 
     fn enqueue(&self, item: T, _lock_type: &mut Self::LockType) {
         self.push(item)
@@ -45,3 +43,20 @@ impl<T> ConcurrentSubQueue<T> for Queue<T> {
         self.pop()
     }
 }
+
+// lockfree creates uninitialized values internally, which Miri rejects as undefined behavior
+impl<T> DefaultSubQueue<T> for Queue<T> {
+    fn new() -> Self {
+        Queue::new()
+    }
+}
+
+#[cfg(all(test, not(loom), not(miri)))]
+mod test {
+    use lockfree::queue::Queue;
+
+    use crate::conformance::{queue_conformance_tests, sub_queue_conformance_tests};
+
+    queue_conformance_tests!(strict, Queue::new());
+    sub_queue_conformance_tests!(strict, Queue::new());
+}
@@ -6,11 +6,29 @@ pub mod ms;
 
 pub trait ConcurrentSubQueue<T> {
     type LockType;
-    /// Creates a new concurrent queue, with default configuration
-    fn new() -> Self;
-    fn new_lock() -> Self::LockType;
+    /// The per-thread state for using the queue. Relaxed queues take its random choices from the
+    /// given stream, see [`register_with_stream`](crate::ConcurrentQueue::register_with_stream).
+    fn new_lock(&self, stream: usize) -> Self::LockType;
     fn enqueue(&self, item: T, lock_type: &mut Self::LockType);
     fn dequeue(&self, lock_type: &mut Self::LockType) -> Option<T>;
+
+    /// Enqueues the items in order, see [`Handle::enqueue_batch`](crate::Handle::enqueue_batch).
+    fn enqueue_batch(&self, items: Vec<T>, lock_type: &mut Self::LockType) {
+        for item in items {
+            self.enqueue(item, lock_type);
+        }
+    }
+
+    /// Dequeues up to `max` items, see [`Handle::dequeue_batch`](crate::Handle::dequeue_batch).
+    fn dequeue_batch(&self, max: usize, lock_type: &mut Self::LockType) -> Vec<T> {
+        (0..max).map_while(|_| self.dequeue(lock_type)).collect()
+    }
+}
+
+/// Sub-queues which need no configuration, so relaxed queues can create them themselves. Relaxed
+/// queues used as sub-queues are configured, and built with `from_subqueues` instead.
+pub trait DefaultSubQueue<T>: ConcurrentSubQueue<T> {
+    fn new() -> Self;
 }
 
 pub trait CountableConcurrentSubQueue<T>: ConcurrentSubQueue<T> {
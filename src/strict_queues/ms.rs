@@ -1,10 +1,15 @@
 use std::mem::MaybeUninit;
 
-use haphazard::{raw::Pointer, AtomicPtr, HazardPointer};
+use crate::{
+    padded::Padded,
+    sync::{
+        self,
+        hazard::{AtomicPtr, HazardPointer},
+    },
+    ConcurrentQueue, Handle, Strict,
+};
 
-use crate::{ConcurrentQueue, Handle, Strict};
-
-use super::ConcurrentSubQueue;
+use super::{ConcurrentSubQueue, DefaultSubQueue};
 
 struct Node<T> {
     next: AtomicPtr<Node<T>>,
@@ -13,8 +18,12 @@ struct Node<T> {
 
 impl<T> Node<T> {
     fn new(data: T) -> Self {
+        Self::with_next(data, core::ptr::null_mut())
+    }
+    /// A node already linked to the rest of an unpublished chain.
+    fn with_next(data: T, next: *mut Node<T>) -> Self {
         Self {
-            next: unsafe { AtomicPtr::new(core::ptr::null_mut()) },
+            next: unsafe { AtomicPtr::new(next) },
             data: MaybeUninit::new(data),
         }
     }
@@ -26,42 +35,73 @@ impl<T> Node<T> {
     }
 }
 
+/// The head and the tail are on separate cache lines, so dequeuers and enqueuers do not
+/// invalidate each other's end of the queue.
 pub struct MSQueue<T> {
-    head: AtomicPtr<Node<T>>,
-    tail: AtomicPtr<Node<T>>,
+    head: Padded<AtomicPtr<Node<T>>>,
+    tail: Padded<AtomicPtr<Node<T>>>,
 }
 
 impl<T> MSQueue<T> {
     pub fn new() -> Self {
-        let sentinel = Box::new(Node::new_uninit()).into_raw();
-        // TODO drop all data in queue when queue dropped
+        let sentinel = Box::into_raw(Box::new(Node::new_uninit()));
         Self {
-            head: unsafe { AtomicPtr::new(sentinel) },
-            tail: unsafe { AtomicPtr::new(sentinel) },
+            head: Padded::new(unsafe { AtomicPtr::new(sentinel) }),
+            tail: Padded::new(unsafe { AtomicPtr::new(sentinel) }),
         }
     }
 }
 
+impl<T> Default for MSQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T: Sync + Send> MSQueue<T> {
     pub fn enqueue(&self, hp: &mut HazardPointer, data: T) {
-        let new_node: *mut Node<T> = Box::new(Node::new(data)).into_raw();
+        let new_node: *mut Node<T> = Box::into_raw(Box::new(Node::new(data)));
+        self.link(hp, new_node, new_node);
+    }
+
+    /// Enqueues the items in order by building a chain of nodes privately, and linking all of it
+    /// to the queue with a single CAS.
+    pub fn enqueue_batch(&self, hp: &mut HazardPointer, items: Vec<T>) {
+        let mut items = items.into_iter().rev();
+        let Some(last_item) = items.next() else {
+            return;
+        };
+        let last: *mut Node<T> = Box::into_raw(Box::new(Node::new(last_item)));
+        let first = items.fold(last, |next, item| {
+            Box::into_raw(Box::new(Node::with_next(item, next)))
+        });
+        self.link(hp, first, last);
+    }
 
+    /// Links the chain from `first` to `last` after the tail, and moves the tail to its end.
+    /// Threads helping a lagging tail move it one node at a time until it reaches the end.
+    fn link(&self, hp: &mut HazardPointer, first: *mut Node<T>, last: *mut Node<T>) {
         let mut tail;
         loop {
+            sync::yield_point();
             tail = self.tail.safe_load(hp).unwrap();
-            // Remove if? We think it is an optimization.
+            // Only skips CASes which would fail anyway, as the protected tail stays valid even
+            // if it moved
+            sync::yield_point();
             if std::ptr::eq(tail, self.tail.load_ptr()) {
+                sync::yield_point();
                 if std::ptr::eq(tail.next.load_ptr(), std::ptr::null_mut()) {
-                    // Why did it not work with compare_exchange here?
-                    if unsafe {
-                        tail.next
-                            .compare_exchange_ptr(std::ptr::null_mut(), new_node)
-                    }
-                    .is_ok()
+                    // The release CAS publishes the initialized chain to dequeuers, which load
+                    // `next` with acquire before reading its data. The chain is raw pointers, so
+                    // this is the pointer CAS rather than the one taking ownership of a box.
+                    sync::yield_point();
+                    if unsafe { tail.next.compare_exchange_ptr(std::ptr::null_mut(), first) }
+                        .is_ok()
                     {
                         break;
                     }
                 } else {
+                    sync::yield_point();
                     unsafe {
                         let _ = self.tail.compare_exchange_ptr(
                             tail as *const Node<T> as *mut Node<T>,
@@ -70,24 +110,30 @@ impl<T: Sync + Send> MSQueue<T> {
                     };
                 }
             };
+            sync::spin_loop();
         }
+        sync::yield_point();
         unsafe {
             let _ = self
                 .tail
-                .compare_exchange_ptr(tail as *const Node<T> as *mut Node<T>, new_node);
+                .compare_exchange_ptr(tail as *const Node<T> as *mut Node<T>, last);
         }
     }
 
     pub fn dequeue(&self, hp_head: &mut HazardPointer, hp_next: &mut HazardPointer) -> Option<T> {
         loop {
+            sync::yield_point();
             let head = self
                 .head
                 .safe_load(hp_head)
                 .expect("MS queue should never be empty");
             let head_ptr = head as *const Node<T>;
+            sync::yield_point();
             let tail_ptr = self.tail.load_ptr();
 
+            sync::yield_point();
             if head_ptr == self.head.load_ptr() {
+                sync::yield_point();
                 let next_ptr = head.next.load_ptr();
                 if head_ptr == tail_ptr {
                     if next_ptr.is_null() {
@@ -95,13 +141,16 @@ impl<T: Sync + Send> MSQueue<T> {
                         return None;
                     } else {
                         // Help the partially completed enqueue
+                        sync::yield_point();
                         unsafe {
                             let _ = self.tail.compare_exchange_ptr(tail_ptr, next_ptr);
                         }
                     }
                 } else {
                     // Non-empty, read next value
+                    sync::yield_point();
                     let next = head.next.safe_load(hp_next).unwrap();
+                    sync::yield_point();
                     if let Ok(unlinked_head_ptr) = unsafe {
                         self.head
                             .compare_exchange_ptr(head_ptr as *mut Node<T>, next_ptr)
@@ -119,14 +168,19 @@ impl<T: Sync + Send> MSQueue<T> {
                     }
                 }
             }
+            sync::spin_loop();
         }
     }
 }
 
 impl<T: Send + Sync> ConcurrentQueue<T> for MSQueue<T> {
     type QueueType = Strict;
+    type Handle<'q>
+        = QueueHandle<'q, T>
+    where
+        T: 'q;
 
-    fn register(&self) -> impl Handle<T> {
+    fn register(&self) -> QueueHandle<'_, T> {
         QueueHandle::new(self)
     }
 }
@@ -168,6 +222,10 @@ impl<'q, T: Sync + Send> QueueHandle<'q, T> {
         self.queue.enqueue(&mut self.hz1, data);
     }
 
+    pub fn enqueue_batch(&mut self, items: Vec<T>) {
+        self.queue.enqueue_batch(&mut self.hz1, items);
+    }
+
     pub fn dequeue(&mut self) -> Option<T> {
         self.queue.dequeue(&mut self.hz1, &mut self.hz2)
     }
@@ -181,13 +239,14 @@ impl<T: Send + Sync> Handle<T> for QueueHandle<'_, T> {
     fn dequeue(&mut self) -> Option<T> {
         QueueHandle::dequeue(self)
     }
+
+    fn enqueue_batch(&mut self, items: Vec<T>) {
+        QueueHandle::enqueue_batch(self, items);
+    }
 }
 
 impl<T: Send + Sync> ConcurrentSubQueue<T> for MSQueue<T> {
     type LockType = (HazardPointer<'static>, HazardPointer<'static>);
-    fn new() -> Self {
-        MSQueue::new()
-    }
 
     fn enqueue(&self, item: T, lock_type: &mut Self::LockType) {
         let (hz, _) = lock_type;
@@ -199,17 +258,36 @@ impl<T: Send + Sync> ConcurrentSubQueue<T> for MSQueue<T> {
         self.dequeue(hz1, hz2)
     }
 
-    fn new_lock() -> Self::LockType {
+    fn enqueue_batch(&self, items: Vec<T>, lock_type: &mut Self::LockType) {
+        let (hz, _) = lock_type;
+        self.enqueue_batch(hz, items);
+    }
+
+    fn new_lock(&self, _stream: usize) -> Self::LockType {
         (HazardPointer::new(), HazardPointer::new())
     }
 }
 
-#[cfg(test)]
+impl<T: Send + Sync> DefaultSubQueue<T> for MSQueue<T> {
+    fn new() -> Self {
+        MSQueue::new()
+    }
+}
+
+#[cfg(all(test, not(loom)))]
 mod test {
     use std::sync::Mutex;
 
+    use crate::{
+        conformance::{queue_conformance_tests, sub_queue_conformance_tests},
+        sync::schedule,
+    };
+
     use super::{MSQueue, QueueHandle};
 
+    queue_conformance_tests!(strict, MSQueue::new());
+    sub_queue_conformance_tests!(strict, MSQueue::new());
+
     #[test]
     fn simple_test() {
         let queue = MSQueue::new();
@@ -344,4 +422,145 @@ mod test {
             assert_eq!(collected_elements[i], i);
         }
     }
+
+    /// Two producers enqueue three items each, the first as a single batch, while a consumer
+    /// dequeues, returning the order in which the items came out, with the items left in the
+    /// queue last.
+    fn scheduled_order(seed: u64) -> Vec<usize> {
+        let queue = MSQueue::new();
+        let mut dequeued = schedule::run(
+            seed,
+            (0..3).map(|id| {
+                let queue = &queue;
+                move || {
+                    let mut qh = QueueHandle::new(queue);
+                    if id == 0 {
+                        qh.enqueue_batch(vec![0, 1, 2]);
+                        Vec::new()
+                    } else if id == 1 {
+                        (0..3).for_each(|i| qh.enqueue(id * 10 + i));
+                        Vec::new()
+                    } else {
+                        (0..6).filter_map(|_| qh.dequeue()).collect()
+                    }
+                }
+            }),
+        )
+        .pop()
+        .unwrap();
+        let mut qh = QueueHandle::new(&queue);
+        dequeued.extend(std::iter::from_fn(|| qh.dequeue()));
+        dequeued
+    }
+
+    #[test]
+    fn scheduled_interleavings() {
+        schedule::explore(200, |seed| {
+            let order = scheduled_order(seed);
+            for producer in [0, 10] {
+                let items: Vec<_> = order.iter().filter(|&&i| i / 10 == producer / 10).collect();
+                assert_eq!(items, [&producer, &(producer + 1), &(producer + 2)]);
+            }
+            assert_eq!(order, scheduled_order(seed), "the schedule should replay");
+        });
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_test {
+    use loom::{model::Builder, sync::Arc, thread};
+
+    use crate::sync::hazard::HazardPointer;
+
+    use super::MSQueue;
+
+    // The head, tail and next pointers are loom atomics here, so loom explores the reorderings
+    // their orderings allow. Retired nodes are never freed, so hazard protection is not checked.
+
+    fn model(f: impl Fn() + Sync + Send + 'static) {
+        let mut builder = Builder::new();
+        builder.preemption_bound = Some(3);
+        builder.check(f);
+    }
+
+    fn hazard_pointers() -> (HazardPointer<'static>, HazardPointer<'static>) {
+        (HazardPointer::new(), HazardPointer::new())
+    }
+
+    #[test]
+    fn concurrent_enqueues() {
+        model(|| {
+            let queue = Arc::new(MSQueue::new());
+            let (mut hp1, mut hp2) = hazard_pointers();
+            let producers: Vec<_> = (0..2)
+                .map(|i| {
+                    let queue = queue.clone();
+                    let mut hp = HazardPointer::new();
+                    thread::spawn(move || queue.enqueue(&mut hp, i))
+                })
+                .collect();
+            for producer in producers {
+                producer.join().unwrap();
+            }
+
+            let mut items = [
+                queue.dequeue(&mut hp1, &mut hp2),
+                queue.dequeue(&mut hp1, &mut hp2),
+            ];
+            items.sort();
+            assert_eq!(items, [Some(0), Some(1)]);
+            assert_eq!(queue.dequeue(&mut hp1, &mut hp2), None);
+        });
+    }
+
+    #[test]
+    fn enqueue_dequeue() {
+        model(|| {
+            let queue = Arc::new(MSQueue::new());
+            let (mut hp1, mut hp2) = hazard_pointers();
+            let producer = {
+                let queue = queue.clone();
+                let mut hp = HazardPointer::new();
+                thread::spawn(move || {
+                    queue.enqueue(&mut hp, 1);
+                    queue.enqueue(&mut hp, 2);
+                })
+            };
+
+            let first = queue.dequeue(&mut hp1, &mut hp2);
+            producer.join().unwrap();
+
+            // Whatever we got must be a prefix of the enqueued items
+            match first {
+                Some(1) => assert_eq!(queue.dequeue(&mut hp1, &mut hp2), Some(2)),
+                None => {
+                    assert_eq!(queue.dequeue(&mut hp1, &mut hp2), Some(1));
+                    assert_eq!(queue.dequeue(&mut hp1, &mut hp2), Some(2));
+                }
+                other => panic!("unexpected first item {other:?}"),
+            }
+            assert_eq!(queue.dequeue(&mut hp1, &mut hp2), None);
+        });
+    }
+
+    #[test]
+    fn concurrent_dequeues() {
+        model(|| {
+            let queue = Arc::new(MSQueue::new());
+            let (mut hp1, mut hp2) = hazard_pointers();
+            queue.enqueue(&mut hp1, Box::new(1));
+
+            let consumer = {
+                let queue = queue.clone();
+                let (mut hp1, mut hp2) = hazard_pointers();
+                thread::spawn(move || queue.dequeue(&mut hp1, &mut hp2))
+            };
+            let mine = queue.dequeue(&mut hp1, &mut hp2);
+            let theirs = consumer.join().unwrap();
+
+            // Exactly one of the dequeues gets the item
+            assert!(mine.is_some() != theirs.is_some());
+            assert_eq!(mine.or(theirs), Some(Box::new(1)));
+        });
+    }
 }
@@ -0,0 +1,102 @@
+//! Synchronization primitives, swapped for their [loom](https://docs.rs/loom) counterparts when
+//! model checking with `RUSTFLAGS="--cfg loom"`, and the scheduling points of replayable tests.
+
+#[cfg(loom)]
+pub(crate) mod atomic {
+    pub(crate) use loom::sync::atomic::{AtomicUsize, Ordering};
+}
+
+#[cfg(not(loom))]
+pub(crate) mod atomic {
+    pub(crate) use std::sync::atomic::{AtomicUsize, Ordering};
+}
+
+/// The hazard pointers of the MS queue. Haphazard works on std atomics which loom cannot see, so
+/// under loom the pointers are loom atomics with the same orderings as haphazard, and hazard
+/// protection is stubbed out by never freeing retired nodes.
+#[cfg(not(loom))]
+pub(crate) mod hazard {
+    pub(crate) use haphazard::{AtomicPtr, HazardPointer};
+}
+
+/// The hazard pointers of the MS queue. Haphazard works on std atomics which loom cannot see, so
+/// under loom the pointers are loom atomics with the same orderings as haphazard, and hazard
+/// protection is stubbed out by never freeing retired nodes.
+#[cfg(loom)]
+pub(crate) mod hazard {
+    use std::marker::PhantomData;
+
+    use loom::sync::atomic::{self, Ordering};
+
+    /// Protects nothing, as no node is freed while the model runs.
+    pub struct HazardPointer<'domain>(PhantomData<&'domain ()>);
+
+    impl HazardPointer<'_> {
+        pub(crate) fn new() -> Self {
+            Self(PhantomData)
+        }
+    }
+
+    pub struct AtomicPtr<T>(atomic::AtomicPtr<T>);
+
+    /// A pointer swapped out of an [`AtomicPtr`], which is leaked instead of retired.
+    pub struct Replaced<T>(PhantomData<*mut T>);
+
+    impl<T> Replaced<T> {
+        pub(crate) unsafe fn retire(self) {}
+    }
+
+    impl<T> AtomicPtr<T> {
+        pub(crate) unsafe fn new(p: *mut T) -> Self {
+            Self(atomic::AtomicPtr::new(p))
+        }
+
+        pub(crate) fn safe_load<'hp>(&self, _hp: &'hp mut HazardPointer<'_>) -> Option<&'hp T> {
+            unsafe { self.load_ptr().as_ref() }
+        }
+
+        pub(crate) fn load_ptr(&self) -> *mut T {
+            self.0.load(Ordering::Acquire)
+        }
+
+        pub(crate) unsafe fn compare_exchange_ptr(
+            &self,
+            current: *mut T,
+            new: *mut T,
+        ) -> Result<Option<Replaced<T>>, *mut T> {
+            let old =
+                self.0
+                    .compare_exchange(current, new, Ordering::Release, Ordering::Relaxed)?;
+            Ok((!old.is_null()).then_some(Replaced(PhantomData)))
+        }
+    }
+}
+
+/// Called when a lock-free loop retries. Loom needs to know that the thread waits for others to
+/// make progress, otherwise it explores executions where it keeps reading stale values forever.
+#[cfg(loom)]
+pub(crate) fn spin_loop() {
+    loom::thread::yield_now();
+}
+
+/// Called when a lock-free loop retries. Loom needs to know that the thread waits for others to
+/// make progress, otherwise it explores executions where it keeps reading stale values forever.
+#[cfg(not(loom))]
+#[inline(always)]
+pub(crate) fn spin_loop() {}
+
+#[cfg(all(test, not(loom)))]
+pub(crate) mod schedule;
+
+/// Called before an atomic operation on shared state, where the cooperative [`schedule`] of a
+/// test may switch to another thread.
+#[cfg(all(test, not(loom)))]
+pub(crate) fn yield_point() {
+    schedule::yield_point();
+}
+
+/// Called before an atomic operation on shared state, where the cooperative scheduler of a test
+/// may switch to another thread. Loom already switches threads at its own atomics.
+#[cfg(any(not(test), loom))]
+#[inline(always)]
+pub(crate) fn yield_point() {}
@@ -0,0 +1,189 @@
+//! A cooperative scheduler for replaying concurrency bugs.
+//!
+//! The threads of a [`run`] are real threads, but only one of them runs at a time. They hand over
+//! at the [yield points](super::yield_point) the queues place at their atomic operations, to a
+//! thread picked from a seeded random stream. As nothing else decides the order, a seed always
+//! gives the same interleaving, so a seed which fails in [`explore`] can be replayed exactly.
+
+use std::{
+    cell::RefCell,
+    env,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    thread,
+};
+
+use rand::{Rng, SeedableRng};
+
+use crate::relaxed_queues::DefaultRng;
+
+/// Set to a seed to only run that schedule in [`explore`].
+const SEED_VARIABLE: &str = "SCHEDULE_SEED";
+
+struct State {
+    /// The thread whose turn it is, if any is left
+    running: Option<usize>,
+    finished: Vec<bool>,
+    rng: DefaultRng,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    turn: Condvar,
+}
+
+thread_local! {
+    /// The schedule of the current thread and its place in it, if it runs under one.
+    static SCHEDULED: RefCell<Option<(Arc<Shared>, usize)>> = const { RefCell::new(None) };
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // A panicking thread still hands over its turn, so the state is never left inconsistent
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Gives the turn to a random unfinished thread, which may be the current one.
+    fn pass(&self, state: &mut State) {
+        let waiting: Vec<usize> = (0..state.finished.len())
+            .filter(|&id| !state.finished[id])
+            .collect();
+        state.running = match waiting.len() {
+            0 => None,
+            len => Some(waiting[state.rng.gen_range(0..len)]),
+        };
+        self.turn.notify_all();
+    }
+
+    fn wait(&self, mut state: MutexGuard<'_, State>, id: usize) {
+        while state.running != Some(id) {
+            state = self
+                .turn
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+/// Finishes the turn of a scheduled thread for good, even if it panics.
+struct Finish(Arc<Shared>, usize);
+
+impl Drop for Finish {
+    fn drop(&mut self) {
+        SCHEDULED.with(|scheduled| scheduled.borrow_mut().take());
+        let mut state = self.0.lock();
+        state.finished[self.1] = true;
+        self.0.pass(&mut state);
+    }
+}
+
+/// Lets another thread of the schedule run, if the current thread runs under one.
+pub(crate) fn yield_point() {
+    let scheduled = SCHEDULED.with(|scheduled| scheduled.borrow().clone());
+    if let Some((shared, id)) = scheduled {
+        let mut state = shared.lock();
+        shared.pass(&mut state);
+        shared.wait(state, id);
+    }
+}
+
+/// Runs the threads interleaved by the schedule of the seed, returning what they return. Panics
+/// if any of them does.
+pub(crate) fn run<F, R>(seed: u64, threads: impl IntoIterator<Item = F>) -> Vec<R>
+where
+    F: FnOnce() -> R + Send,
+    R: Send,
+{
+    let threads: Vec<F> = threads.into_iter().collect();
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            running: None,
+            finished: vec![false; threads.len()],
+            rng: DefaultRng::seed_from_u64(seed),
+        }),
+        turn: Condvar::new(),
+    });
+
+    let results: Vec<thread::Result<R>> = thread::scope(|s| {
+        let handles: Vec<_> = threads
+            .into_iter()
+            .enumerate()
+            .map(|(id, thread)| {
+                let shared = shared.clone();
+                s.spawn(move || {
+                    let _finish = Finish(shared.clone(), id);
+                    SCHEDULED
+                        .with(|scheduled| *scheduled.borrow_mut() = Some((shared.clone(), id)));
+                    shared.wait(shared.lock(), id);
+                    thread()
+                })
+            })
+            .collect();
+        shared.pass(&mut shared.lock());
+        handles.into_iter().map(|handle| handle.join()).collect()
+    });
+    results
+        .into_iter()
+        .map(|result| result.unwrap_or_else(|panic| panic::resume_unwind(panic)))
+        .collect()
+}
+
+/// Runs the test with the seeds of many schedules, naming the seed of the first one which fails
+/// so it can be replayed with [`run`], or by setting `SCHEDULE_SEED` to only run that seed.
+pub(crate) fn explore(schedules: u64, test: impl Fn(u64)) {
+    let seeds = match env::var(SEED_VARIABLE) {
+        Ok(seed) => {
+            let seed = seed.parse().expect("SCHEDULE_SEED should be a number");
+            seed..seed + 1
+        }
+        Err(_) => 0..schedules,
+    };
+    for seed in seeds {
+        if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| test(seed))) {
+            eprintln!("failed with schedule seed {seed}, replay with {SEED_VARIABLE}={seed}");
+            panic::resume_unwind(panic);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::{run, yield_point};
+
+    /// The order in which three threads take their steps.
+    fn interleaving(seed: u64) -> Vec<usize> {
+        let log = Mutex::new(Vec::new());
+        run(
+            seed,
+            (0..3).map(|id| {
+                let log = &log;
+                move || {
+                    for _ in 0..10 {
+                        log.lock().unwrap().push(id);
+                        yield_point();
+                    }
+                }
+            }),
+        );
+        log.into_inner().unwrap()
+    }
+
+    #[test]
+    fn replays_interleavings() {
+        assert_eq!(interleaving(3), interleaving(3));
+        assert!((0..10).any(|seed| interleaving(seed) != interleaving(3)));
+        assert_eq!(interleaving(3).len(), 30);
+    }
+
+    #[test]
+    fn returns_results_and_panics() {
+        assert_eq!(run(0, (0..4).map(|id| move || id * 2)), [0, 2, 4, 6]);
+
+        let panicked = std::panic::catch_unwind(|| {
+            run(0, (0..2).map(|id| move || assert_ne!(id, 1)));
+        });
+        assert!(panicked.is_err());
+    }
+}